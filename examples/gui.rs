@@ -93,7 +93,7 @@ struct CrnAppState {
     dt: f64,
     simulation_length: f64,
     reactions: String,
-    error: Option<crn::Error>,
+    error: Option<String>,
     crn_type: CrnTypes,
     desc: &'static str,
 }
@@ -112,16 +112,18 @@ impl App for CrnApp {
                 if ui.button("Parse").clicked() {
                     match self.state.crn_type {
                         CrnTypes::Sto => match crn::StoCrn::parse(&self.state.reactions) {
-                            Ok(crn) => self.crn = CrnSim::Sto(crn),
-                            Err(e) => {
-                                println!("Error: {:?}", e);
+                            Ok(crn) => {
+                                self.crn = CrnSim::Sto(crn);
+                                self.state.error = None;
                             }
+                            Err(e) => self.state.error = Some(e.to_string()),
                         },
                         CrnTypes::Det => match crn::DetCrn::parse(&self.state.reactions) {
-                            Ok(crn) => self.crn = CrnSim::Det(crn),
-                            Err(e) => {
-                                println!("Error: {:?}", e);
+                            Ok(crn) => {
+                                self.crn = CrnSim::Det(crn);
+                                self.state.error = None;
                             }
+                            Err(e) => self.state.error = Some(e.to_string()),
                         },
                     }
                 }
@@ -152,16 +154,18 @@ impl App for CrnApp {
 
                             match self.state.crn_type {
                                 CrnTypes::Sto => match crn::StoCrn::parse(&self.state.reactions) {
-                                    Ok(crn) => self.crn = CrnSim::Sto(crn),
-                                    Err(e) => {
-                                        println!("Error: {:?}", e);
+                                    Ok(crn) => {
+                                        self.crn = CrnSim::Sto(crn);
+                                        self.state.error = None;
                                     }
+                                    Err(e) => self.state.error = Some(e.to_string()),
                                 },
                                 CrnTypes::Det => match crn::DetCrn::parse(&self.state.reactions) {
-                                    Ok(crn) => self.crn = CrnSim::Det(crn),
-                                    Err(e) => {
-                                        println!("Error: {:?}", e);
+                                    Ok(crn) => {
+                                        self.crn = CrnSim::Det(crn);
+                                        self.state.error = None;
                                     }
+                                    Err(e) => self.state.error = Some(e.to_string()),
                                 },
                             }
                             // :( I don't know how to avoid this after getting rid of the trait
@@ -196,7 +200,7 @@ impl App for CrnApp {
                         };
                         self.state.error = None;
                     }
-                    Err(s) => self.state.error = Some(s),
+                    Err(s) => self.state.error = Some(s.to_string()),
                 }
                 match &self.crn {
                     CrnSim::Sto(crn) => println!("{:?}", crn.state),
@@ -206,21 +210,27 @@ impl App for CrnApp {
 
             if ui.button(self.state.crn_type.to_string()).clicked() {
                 match self.state.crn_type {
-                    CrnTypes::Sto => {
-                        self.state.crn_type = CrnTypes::Det;
-                        self.crn = CrnSim::Det(crn::DetCrn::parse(&self.state.reactions).unwrap());
-                    }
-                    CrnTypes::Det => {
-                        self.state.crn_type = CrnTypes::Sto;
-                        self.crn = CrnSim::Sto(crn::StoCrn::parse(&self.state.reactions).unwrap());
-                    }
+                    CrnTypes::Sto => match crn::DetCrn::parse(&self.state.reactions) {
+                        Ok(crn) => {
+                            self.state.crn_type = CrnTypes::Det;
+                            self.crn = CrnSim::Det(crn);
+                        }
+                        Err(e) => self.state.error = Some(e.to_string()),
+                    },
+                    CrnTypes::Det => match crn::StoCrn::parse(&self.state.reactions) {
+                        Ok(crn) => {
+                            self.state.crn_type = CrnTypes::Sto;
+                            self.crn = CrnSim::Sto(crn);
+                        }
+                        Err(e) => self.state.error = Some(e.to_string()),
+                    },
                 }
             }
 
             self.state
                 .error
                 .as_ref()
-                .map(|e| ui.label(format!("Error: {:?}", e)));
+                .map(|e| ui.label(format!("Error: {}", e)));
             // ui.label(format!("Error: {:?}", self.state.error));
 
             ui.label("Simulation length");
@@ -0,0 +1,710 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{Display, Write};
+use std::str::FromStr;
+
+use itertools::Itertools;
+
+use crate::{
+    expr::{format_number, BinOp, Expr},
+    parse::{check_coefficient, parse_expression, Location, ParseError},
+    sbml::Ids,
+    Crn, ExportError, KineticLaw, Reaction,
+};
+
+/// Words with a meaning in Antimony, which the writer does not use as names.
+const KEYWORDS: [&str; 17] = [
+    "model",
+    "module",
+    "end",
+    "species",
+    "compartment",
+    "const",
+    "var",
+    "in",
+    "at",
+    "function",
+    "import",
+    "unit",
+    "formula",
+    "is",
+    "has",
+    "delete",
+    "substanceOnly",
+];
+
+/// Statements that have no counterpart in a CRN, by their first word.
+const UNSUPPORTED: [(&str, &str); 5] = [
+    ("function", "functions"),
+    ("unit", "units"),
+    ("import", "imports"),
+    ("at", "events"),
+    ("delete", "deletions"),
+];
+
+/// Replaces `//`, `#` and `/* */` comments with spaces. Every other byte stays where it was, so locations in the result are valid in the original.
+fn strip_comments(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(['/', '#']) {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let len = if rest.starts_with("//") || rest.starts_with('#') {
+            rest.find('\n').unwrap_or(rest.len())
+        } else if rest.starts_with("/*") {
+            rest.find("*/").map_or(rest.len(), |end| end + 2)
+        } else {
+            out.push('/');
+            rest = &rest[1..];
+            continue;
+        };
+        for c in rest[..len].chars() {
+            match c {
+                '\n' => out.push('\n'),
+                c => out.extend(std::iter::repeat_n(' ', c.len_utf8())),
+            }
+        }
+        rest = &rest[len..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Checks whether `name` is an Antimony identifier: a letter or underscore, then letters, digits and underscores.
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// A species in a reaction, with its coefficient and the text of the coefficient, which is empty if it was left out.
+type Term<'a> = (&'a str, f64, &'a str);
+
+/// A reaction as written, before its names are resolved.
+struct RawReaction<'a> {
+    /// Reactant names and coefficients, with the coefficients as written.
+    reactants: Vec<Term<'a>>,
+    /// Product names and coefficients, with the coefficients as written.
+    products: Vec<Term<'a>>,
+    /// Whether the reaction was written with `->`, which Antimony treats as reversible.
+    reversible: bool,
+    /// The rate law.
+    rate: &'a str,
+}
+
+/// Collects the statements of an Antimony model, then assembles them into a CRN.
+struct Reader<'a> {
+    /// The model text with comments blanked out.
+    text: &'a str,
+    /// Species names, in order of first appearance.
+    species: Vec<&'a str>,
+    /// The compartment of each species declared in one.
+    compartment_of: HashMap<&'a str, &'a str>,
+    /// Species declared `substanceOnly`, whose values are amounts rather than concentrations.
+    substance_only: HashSet<&'a str>,
+    /// Declared compartments.
+    compartments: HashSet<&'a str>,
+    /// Assignments of initial values, in order.
+    assignments: Vec<(&'a str, &'a str)>,
+    /// Reactions, in order.
+    reactions: Vec<RawReaction<'a>>,
+    /// Names written with a leading `$`, and species declared `const`.
+    boundary: HashSet<&'a str>,
+}
+
+impl<'a> Reader<'a> {
+    /// An error for a missing or malformed piece of a statement.
+    fn syntax(&self, fragment: &str, expected: &str) -> ParseError {
+        ParseError::Syntax {
+            expected: expected.to_string(),
+            location: Location::new(self.text, fragment),
+        }
+    }
+
+    /// Reads a species, parameter or compartment name. A name written with a leading `$` is a boundary species, wherever it appears.
+    fn name(&mut self, text: &'a str) -> Result<&'a str, ParseError> {
+        let name = text.trim();
+        let name = match name.strip_prefix('$') {
+            Some(name) => {
+                self.boundary.insert(name);
+                name
+            }
+            None => name,
+        };
+        if !is_identifier(name) {
+            return Err(self.syntax(text.trim_start(), "a name"));
+        }
+        Ok(name)
+    }
+
+    /// Records a species if it has not been seen before.
+    fn add_species(&mut self, name: &'a str) {
+        if !self.species.contains(&name) {
+            self.species.push(name);
+        }
+    }
+
+    /// Reads one side of a reaction, such as `2 A + B`.
+    fn side(&mut self, side: &'a str) -> Result<Vec<Term<'a>>, ParseError> {
+        if side.trim().is_empty() {
+            return Ok(Vec::new());
+        }
+        side.split('+')
+            .map(|term| {
+                let term = term.trim_start();
+                let split = term
+                    .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+                    .unwrap_or(term.len());
+                let (coefficient, name) = term.split_at(split);
+                let name = self.name(name)?;
+                self.add_species(name);
+                if coefficient.is_empty() {
+                    return Ok((name, 1.0, coefficient));
+                }
+                match coefficient.parse::<f64>() {
+                    Ok(x) if x.is_finite() && x > 0.0 => Ok((name, x, coefficient)),
+                    _ => Err(ParseError::InvalidCoefficient {
+                        text: coefficient.to_string(),
+                        location: Location::new(self.text, coefficient),
+                    }),
+                }
+            })
+            .collect()
+    }
+
+    /// Reads a reaction such as `J1: A + B -> C`, whose rate law is `rate`.
+    fn reaction(&mut self, stmt: &'a str, rate: &'a str) -> Result<(), ParseError> {
+        let body = match stmt.split_once(':') {
+            Some((label, body)) if is_identifier(label.trim()) => body,
+            _ => stmt,
+        };
+        let (lhs, rhs, reversible) = match (body.split_once("->"), body.split_once("=>")) {
+            (Some((lhs, rhs)), _) => (lhs, rhs, true),
+            (None, Some((lhs, rhs))) => (lhs, rhs, false),
+            (None, None) => unreachable!("reactions contain an arrow"),
+        };
+        let reactants = self.side(lhs)?;
+        let products = self.side(rhs)?;
+        self.reactions.push(RawReaction {
+            reactants,
+            products,
+            reversible,
+            rate,
+        });
+        Ok(())
+    }
+
+    /// Reads a `species` or `compartment` declaration, such as `species A in cell, B = 5`, after any modifier such as `const`. Species declared `substanceOnly` are recorded as such, and `const` species are boundary species, as their amounts never change.
+    fn declaration(&mut self, kind: &str, list: &'a str, modifier: &str) -> Result<(), ParseError> {
+        for item in list.split(',') {
+            let (item, value) = match item.split_once('=') {
+                Some((item, value)) => (item, Some(value.trim())),
+                None => (item, None),
+            };
+            let (name, compartment) = match item.split_once(" in ") {
+                Some((name, compartment)) => (name, Some(self.name(compartment)?)),
+                None => (item, None),
+            };
+            let name = self.name(name)?;
+            if kind == "species" {
+                self.add_species(name);
+                match modifier {
+                    "substanceOnly" => {
+                        self.substance_only.insert(name);
+                    }
+                    "const" => {
+                        self.boundary.insert(name);
+                    }
+                    _ => {}
+                }
+                if let Some(compartment) = compartment {
+                    self.compartment_of.insert(name, compartment);
+                }
+            } else {
+                self.compartments.insert(name);
+            }
+            if let Some(value) = value {
+                self.assignments.push((name, value));
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads any statement other than a reaction.
+    fn statement(&mut self, stmt: &'a str) -> Result<(), ParseError> {
+        let (word, rest) = stmt
+            .split_once(|c: char| c.is_whitespace() || c == '(')
+            .unwrap_or((stmt, ""));
+        let unsupported = |construct: &str| {
+            Err(ParseError::Unsupported {
+                construct: construct.to_string(),
+                location: Location::new(self.text, stmt),
+            })
+        };
+        match word {
+            "model" | "end" => Ok(()),
+            "const" | "var" | "substanceOnly" => match rest.trim_start().split_once(' ') {
+                Some((kind @ ("species" | "compartment"), list)) => {
+                    self.declaration(kind, list, word)
+                }
+                _ => Ok(()),
+            },
+            "species" | "compartment" => self.declaration(word, rest, ""),
+            _ => {
+                if let Some((_, construct)) = UNSUPPORTED.iter().find(|(kw, _)| *kw == word) {
+                    return unsupported(construct);
+                }
+                if stmt.contains(":=") {
+                    return unsupported("assignment rules");
+                }
+                if let Some((_, body)) = stmt.split_once(':') {
+                    if body.trim_start().starts_with("at ") || body.trim_start().starts_with("at(")
+                    {
+                        return unsupported("events");
+                    }
+                }
+                match stmt.split_once('=') {
+                    Some((name, _)) if name.trim_end().ends_with('\'') => unsupported("rate rules"),
+                    Some((name, value)) if !value.trim().is_empty() => {
+                        let name = self.name(name)?;
+                        self.assignments.push((name, value.trim()));
+                        Ok(())
+                    }
+                    Some(_) => Err(self.syntax(&stmt[stmt.len()..], "a value")),
+                    None => Err(self.syntax(stmt, "a reaction, declaration or assignment")),
+                }
+            }
+        }
+    }
+
+    /// Reads every statement of the model. Statements end at a `;` or the end of the line, and a reaction's rate law follows it after a `;`.
+    fn read(&mut self) -> Result<(), ParseError> {
+        for line in self.text.lines() {
+            let mut segments = line.split(';');
+            while let Some(segment) = segments.next() {
+                let stmt = segment.trim();
+                if stmt.is_empty() {
+                    continue;
+                }
+                if stmt.contains("->") || stmt.contains("=>") {
+                    let rate = segments
+                        .next()
+                        .map(str::trim)
+                        .filter(|rate| !rate.is_empty())
+                        .ok_or_else(|| {
+                            self.syntax(&segment[segment.len()..], "`;` and a rate law")
+                        })?;
+                    self.reaction(stmt, rate)?;
+                } else {
+                    self.statement(stmt)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Evaluates a constant expression over parameters and compartment sizes.
+    fn constant(
+        &self,
+        text: &str,
+        params: &BTreeMap<String, f64>,
+        sizes: &HashMap<&str, f64>,
+    ) -> Result<f64, ParseError> {
+        let expr = parse_expression(self.text, text, &|name| {
+            params
+                .get(name)
+                .map(|value| Expr::Param(name.to_string(), *value))
+                .or_else(|| sizes.get(name).map(|size| Expr::Num(*size)))
+        })?;
+        Ok(expr.eval::<f64>(&[]))
+    }
+
+    /// Assembles the statements into a CRN.
+    fn build<T: Default + Clone + FromStr>(self) -> Result<Crn<T>, ParseError> {
+        let species: HashMap<&str, usize> = self
+            .species
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (*name, i))
+            .collect();
+
+        let mut amounts = HashMap::new();
+        let mut constants = Vec::new();
+        let mut assigned = HashSet::new();
+        for (name, value) in &self.assignments {
+            if !assigned.insert(*name) {
+                return Err(ParseError::DuplicateDefinition {
+                    name: name.to_string(),
+                    location: Location::new(self.text, name),
+                });
+            }
+            if species.contains_key(name) {
+                amounts.insert(*name, *value);
+            } else {
+                constants.push((*name, *value));
+            }
+        }
+
+        // Parameters and compartment sizes can be assigned in any order, so each is evaluated once the ones it refers to are.
+        let mut params = BTreeMap::new();
+        let mut sizes = HashMap::new();
+        while !constants.is_empty() {
+            let mut pending = Vec::new();
+            let mut first_err = None;
+            for (name, text) in &constants {
+                match self.constant(text, &params, &sizes) {
+                    Ok(value) if self.compartments.contains(name) => {
+                        sizes.insert(*name, value);
+                    }
+                    Ok(value) => {
+                        params.insert(name.to_string(), value);
+                    }
+                    Err(err) => {
+                        first_err.get_or_insert(err);
+                        pending.push((*name, *text));
+                    }
+                }
+            }
+            if pending.len() == constants.len() {
+                // nothing could be evaluated, so a name is undefined or the assignments refer to each other
+                return Err(first_err.unwrap());
+            }
+            constants = pending;
+        }
+
+        // the size of the compartment of each species whose value is a concentration
+        let size = |name: &str| {
+            if self.substance_only.contains(name) {
+                return 1.0;
+            }
+            self.compartment_of
+                .get(name)
+                .and_then(|compartment| sizes.get(compartment))
+                .copied()
+                .unwrap_or(1.0)
+        };
+        let mut names = bimap::BiHashMap::new();
+        let mut initial = Vec::new();
+        for (i, name) in self.species.iter().enumerate() {
+            names.insert(i, name.to_string());
+            let Some(text) = amounts.get(name) else {
+                initial.push(T::default());
+                continue;
+            };
+            let amount = (self.constant(text, &params, &sizes)? * size(name)).to_string();
+            initial.push(amount.parse().map_err(|_| ParseError::InvalidNumber {
+                text: amount,
+                location: Location::new(self.text, text),
+            })?);
+        }
+
+        let stoichiometry = |terms: &[Term]| -> Result<HashMap<usize, f64>, ParseError> {
+            let mut result = HashMap::new();
+            for (name, count, text) in terms {
+                let count = check_coefficient::<T>(*count, text, Location::new(self.text, text))?;
+                *result.entry(species[name]).or_insert(0.0) += count;
+            }
+            Ok(result)
+        };
+        let mut rxns = Vec::new();
+        for raw in &self.reactions {
+            let reactants = stoichiometry(&raw.reactants)?;
+            let products = stoichiometry(&raw.products)?;
+            let law = parse_expression(self.text, raw.rate, &|name| {
+                species
+                    .get(name)
+                    .map(|i| {
+                        let size = size(name);
+                        if size == 1.0 {
+                            Expr::Species(*i)
+                        } else {
+                            Expr::BinOp(
+                                BinOp::Div,
+                                Box::new(Expr::Species(*i)),
+                                Box::new(Expr::Num(size)),
+                            )
+                        }
+                    })
+                    .or_else(|| {
+                        params
+                            .get(name)
+                            .map(|value| Expr::Param(name.to_string(), *value))
+                    })
+                    .or_else(|| sizes.get(name).map(|size| Expr::Num(*size)))
+            })?;
+            let idx = rxns.len();
+            match Reaction::pair_from_law(&law, &reactants, &products).filter(|_| raw.reversible) {
+                Some([mut forward, mut reverse]) => {
+                    forward.reverse = Some(idx + 1);
+                    reverse.reverse = Some(idx);
+                    rxns.extend([forward, reverse]);
+                }
+                None => rxns.push(Reaction::with_law(reactants, products, law)),
+            }
+        }
+
+        let boundary = (0..self.species.len()).filter(|i| self.boundary.contains(self.species[*i]));
+        Ok(Crn::from_parts(rxns, initial, names, params, boundary))
+    }
+}
+
+impl<T> Crn<T>
+where
+    T: Default + Clone + FromStr,
+{
+    /// Reads a CRN from an Antimony model, such as `J1: A -> B; k1*A; A = 10; k1 = 0.5`.
+    ///
+    /// Reactions, species and compartment declarations, and initial values are supported, and statements may end at a `;` or a line break. Rate laws of the form `k1*A*B^2` become mass-action reactions, and a reaction written with `->` whose rate law is the difference of two such terms becomes a reversible pair. Other rate laws become custom kinetic laws. Names that are assigned but never used as species become parameters. Amounts are counted in substance units, so the value of a species in a compartment is its concentration, multiplied by the compartment's size to give its initial amount, and divided again where the species appears in a rate law. Species declared `substanceOnly` are not converted. Species written with a leading `$` are boundary species. Events, rules and functions are rejected with [`ParseError::Unsupported`].
+    pub fn from_antimony(text: &str) -> Result<Crn<T>, ParseError> {
+        let text = strip_comments(text);
+        let mut reader = Reader {
+            text: &text,
+            species: Vec::new(),
+            compartment_of: HashMap::new(),
+            substance_only: HashSet::new(),
+            compartments: HashSet::new(),
+            assignments: Vec::new(),
+            reactions: Vec::new(),
+            boundary: HashSet::new(),
+        };
+        reader.read()?;
+        reader.build()
+    }
+}
+
+impl<T> Crn<T>
+where
+    T: Display,
+{
+    /// Writes the CRN's initial state and reactions as an Antimony model.
+    ///
+    /// Species and parameter names that are not valid Antimony identifiers are changed to ones that are. Reversible pairs of mass-action reactions are written as one reaction with `->`, and all other reactions with `=>`. CRNs with events, or with initial amounts given by expressions, are rejected with [`ExportError::Unsupported`].
+    pub fn to_antimony(&self) -> Result<String, ExportError> {
+        self.check_export("Antimony")?;
+        let mut ids = Ids::reserving(&KEYWORDS);
+        let param_ids: HashMap<&str, String> = self
+            .params
+            .keys()
+            .map(|name| (name.as_str(), ids.allocate(name)))
+            .collect();
+        let species_ids: Vec<String> = (0..self.init_state.species.len())
+            .map(|i| match self.names.get_by_left(&i) {
+                Some(name) => ids.allocate(name),
+                None => ids.allocate(&format!("s{i}")),
+            })
+            .collect();
+        let side = |stoichiometry: &HashMap<usize, f64>| {
+            stoichiometry
+                .iter()
+                .sorted_by_key(|(species, _)| **species)
+                .map(|(species, count)| {
+                    if *count == 1.0 {
+                        species_ids[*species].clone()
+                    } else {
+                        format!("{count} {}", species_ids[*species])
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(" + ")
+        };
+        let law = |expr: &Expr| {
+            expr.expand()
+                .format_with(&|i| species_ids[i].clone(), &|name, value| {
+                    param_ids
+                        .get(name)
+                        .cloned()
+                        .unwrap_or_else(|| format_number(value))
+                })
+        };
+
+        let mut out = String::new();
+        let _ = writeln!(out, "model crn()");
+        if !species_ids.is_empty() {
+            let declared: Vec<String> = species_ids
+                .iter()
+                .enumerate()
+                .map(|(i, id)| {
+                    if self.boundary.contains(&i) {
+                        format!("${id}")
+                    } else {
+                        id.clone()
+                    }
+                })
+                .collect();
+            let _ = writeln!(out, "  species {};", declared.join(", "));
+        }
+
+        if !self.rxns.is_empty() {
+            let _ = writeln!(out);
+            for (i, rxn) in self.rxns.iter().enumerate() {
+                let reverse = rxn.reverse.filter(|j| {
+                    self.is_reversible_pair(i, *j)
+                        && rxn.law == KineticLaw::MassAction
+                        && self.rxns[*j].law == KineticLaw::MassAction
+                });
+                let (arrow, rate) = match reverse {
+                    Some(j) if j < i => continue,
+                    Some(j) => (
+                        "->",
+                        Expr::BinOp(
+                            BinOp::Sub,
+                            Box::new(rxn.rate_law()),
+                            Box::new(self.rxns[j].rate_law()),
+                        ),
+                    ),
+                    None => ("=>", rxn.rate_law()),
+                };
+                let _ = writeln!(
+                    out,
+                    "  {}: {} {arrow} {}; {};",
+                    ids.allocate(&format!("J{i}")),
+                    side(&rxn.reactants),
+                    side(&rxn.products),
+                    law(&rate)
+                );
+            }
+        }
+
+        if !species_ids.is_empty() || !self.params.is_empty() {
+            let _ = writeln!(out);
+        }
+        for (id, amount) in species_ids.iter().zip(&self.init_state.species) {
+            let _ = writeln!(out, "  {id} = {amount};");
+        }
+        for (name, value) in &self.params {
+            let _ = writeln!(out, "  {} = {value};", param_ids[name.as_str()]);
+        }
+        let _ = writeln!(out, "end");
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parse::ParseError, presets, DetCrn, ExportError, KineticLaw, StoCrn};
+
+    #[test]
+    fn roundtrip() {
+        for preset in [presets::EQUILIBRIUM, presets::PREDATOR_PREY, presets::OTHER] {
+            let crn = StoCrn::parse(preset).unwrap();
+            let imported = StoCrn::from_antimony(&crn.to_antimony().unwrap()).unwrap();
+            assert_eq!(imported.rxns, crn.rxns);
+            assert_eq!(imported.init_state, crn.init_state);
+            assert_eq!(imported.names, crn.names);
+        }
+
+        let crn = StoCrn::parse("$F = 5; A = 1; F + A -> 2A;").unwrap();
+        let imported = StoCrn::from_antimony(&crn.to_antimony().unwrap()).unwrap();
+        assert_eq!(imported.boundary, crn.boundary);
+        assert_eq!(imported.rxns, crn.rxns);
+        let imported = StoCrn::from_antimony("$F -> A; k*F; F = 3; k = 1").unwrap();
+        assert_eq!(imported.boundary.iter().collect::<Vec<_>>(), [&0]);
+        assert_eq!(imported.names.get_by_left(&0).unwrap(), "F");
+
+        let crn = DetCrn::parse("param k = 2; A = 1.5; A -> B : k; B -> : mm(B, k, 3);").unwrap();
+        let imported = DetCrn::from_antimony(&crn.to_antimony().unwrap()).unwrap();
+        assert_eq!(imported.params, crn.params);
+        assert_eq!(imported.rxns[0], crn.rxns[0]);
+        assert!(matches!(imported.rxns[1].law, KineticLaw::Custom(_)));
+        assert_eq!(
+            imported.state.rate(&imported.rxns[1]),
+            crn.state.rate(&crn.rxns[1])
+        );
+
+        let crn = DetCrn::parse(
+            "module m(S) { param k = 3; S -> : k * S / (1 + S); } A = 1; c: m(A); param species = 5; A -> B : species;",
+        )
+        .unwrap();
+        let text = crn.to_antimony().unwrap();
+        assert!(!text.contains('"'));
+        let imported = DetCrn::from_antimony(&text).unwrap();
+        let rates =
+            |crn: &DetCrn| -> Vec<f64> { crn.rxns.iter().map(|rxn| crn.state.rate(rxn)).collect() };
+        assert_eq!(rates(&imported), vec![1.5, 5.0]);
+        assert_eq!(rates(&imported), rates(&crn));
+    }
+
+    #[test]
+    fn import() {
+        let text = "// Created by libAntimony
+model *example()
+  compartment cell;
+  substanceOnly species S in cell, P in cell;
+  species E;
+
+  /* Reactions */
+  J0: S + E -> P + E; kf*S*E - kr*P*E
+  J1: 2 P => ; 0.5*kd*P^2;
+
+  S = 5; P = 0
+  E = 1;
+  cell = 2;
+  kf = 0.1; kr = 0.05
+  kd = 1;
+end
+";
+        let crn = StoCrn::from_antimony(text).unwrap();
+        assert_eq!(crn.init_state.species, vec![5, 0, 1]);
+        assert_eq!(crn.names.get_by_left(&1).unwrap(), "P");
+        assert_eq!(crn.rxns.len(), 3);
+        assert_eq!(crn.rxns[0].rate_param.as_deref(), Some("kf"));
+        assert_eq!(crn.rxns[1].rate_param.as_deref(), Some("kr"));
+        assert_eq!(crn.rxns[1].reverse, Some(0));
+        assert_eq!(crn.rxns[2].reactants[&1], 2.0);
+        // a constant factor keeps the law custom, so that it still follows `kd`
+        let mut crn = crn;
+        assert!(matches!(crn.rxns[2].law, KineticLaw::Custom(_)));
+        crn.state.species[1] = 2;
+        assert_eq!(crn.state.rate(&crn.rxns[2]), 2.0);
+        crn.set_parameter("kd", 2.0).unwrap();
+        assert_eq!(crn.state.rate(&crn.rxns[2]), 4.0);
+
+        // the concentration of `S` is 5, and its amount 10
+        let text = "compartment c = 2; species S in c; S = 5; k = 1; J0: S => ; c*k*S";
+        let crn = DetCrn::from_antimony(text).unwrap();
+        assert_eq!(crn.init_state.species, vec![10.0]);
+        assert_eq!(crn.state.rate(&crn.rxns[0]), 10.0);
+
+        // parameters can refer to ones assigned later, and `const` species never change
+        let text = "const species F; F = 3; F -> A; k2*F; k2 = 2 * k1; k1 = 1.5;";
+        let crn = StoCrn::from_antimony(text).unwrap();
+        assert_eq!(crn.params["k2"], 3.0);
+        assert!(crn.boundary.contains(&0));
+        assert!(!crn.rxns[0].delta.contains_key(&0));
+        assert!(matches!(
+            StoCrn::from_antimony("A -> ; a*A; a = b; b = a;"),
+            Err(ParseError::UnknownParameter { .. })
+        ));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(
+            StoCrn::from_antimony("A -> B\nk = 1"),
+            Err(ParseError::Syntax { expected, location }) if expected == "`;` and a rate law" && location.line == 1
+        ));
+        assert!(matches!(
+            StoCrn::from_antimony("A = 1;\nE1: at (time > 5): A = 0;"),
+            Err(ParseError::Unsupported { construct, location }) if construct == "events" && location.line == 2
+        ));
+        assert!(matches!(
+            StoCrn::from_antimony("A -> B; k*A"),
+            Err(ParseError::UnknownParameter { name, .. }) if name == "k"
+        ));
+        assert!(matches!(
+            StoCrn::from_antimony("1.5 A -> B; 1"),
+            Err(ParseError::FractionalCoefficient { .. })
+        ));
+        assert!(matches!(
+            StoCrn::from_antimony("0 A -> B; 1"),
+            Err(ParseError::InvalidCoefficient { .. })
+        ));
+        let crn = DetCrn::from_antimony("1.5 A -> B; 1").unwrap();
+        assert_eq!(crn.rxns[0].reactants[&0], 1.5);
+
+        let crn = StoCrn::parse("param N = 2; A = 2 * N; A -> B;").unwrap();
+        assert!(matches!(
+            crn.to_antimony(),
+            Err(ExportError::Unsupported { construct, .. }) if construct == "initial amounts given by expressions"
+        ));
+    }
+}
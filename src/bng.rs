@@ -0,0 +1,275 @@
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+
+use crate::{
+    expr::Expr,
+    parse::{parse_expression, Location, ParseError},
+    Crn, Reaction,
+};
+
+/// Splits the first `n` whitespace-separated fields off `line`, and returns them with the rest of the line.
+fn fields(line: &str, n: usize) -> (Vec<&str>, &str) {
+    let mut rest = line.trim_start();
+    let mut fields = Vec::with_capacity(n);
+    while fields.len() < n && !rest.is_empty() {
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        fields.push(&rest[..end]);
+        rest = rest[end..].trim_start();
+    }
+    (fields, rest.trim_end())
+}
+
+/// Converts the blocks of a `.net` file into the parts of a CRN.
+struct Importer<'a, T> {
+    /// The file contents, used to locate errors.
+    text: &'a str,
+    /// Parameter values.
+    params: BTreeMap<String, f64>,
+    /// Index of each species, by its number in the file.
+    species: HashMap<&'a str, usize>,
+    /// Names of the species, by index.
+    names: bimap::BiHashMap<usize, String>,
+    /// Initial amount of each species.
+    amounts: Vec<T>,
+    /// Reactions read so far.
+    rxns: Vec<Reaction>,
+    /// Fixed species, whose names start with `$`.
+    boundary: Vec<usize>,
+}
+
+impl<'a, T> Importer<'a, T>
+where
+    T: FromStr,
+{
+    /// An error for a line that does not have the expected fields.
+    fn malformed(&self, line: &'a str, expected: &str) -> ParseError {
+        ParseError::Syntax {
+            expected: expected.to_string(),
+            location: Location::new(self.text, line),
+        }
+    }
+
+    /// Evaluates an expression over the parameters declared so far.
+    fn expr(&self, text: &'a str) -> Result<Expr, ParseError> {
+        parse_expression(self.text, text, &|name| {
+            self.params
+                .get(name)
+                .map(|value| Expr::Param(name.to_string(), *value))
+        })
+    }
+
+    /// Reads a line of the `parameters` block: `index name value`.
+    fn parameter(&mut self, line: &'a str) -> Result<(), ParseError> {
+        let (fields, value) = fields(line, 2);
+        if fields.len() < 2 || value.is_empty() {
+            return Err(self.malformed(line, "a parameter index, name and value"));
+        }
+        let value = self.expr(value)?.eval::<f64>(&[]);
+        if self.params.insert(fields[1].to_string(), value).is_some() {
+            return Err(ParseError::DuplicateDefinition {
+                name: fields[1].to_string(),
+                location: Location::new(self.text, fields[1]),
+            });
+        }
+        Ok(())
+    }
+
+    /// Reads a line of the `species` block: `index name amount`.
+    fn species(&mut self, line: &'a str) -> Result<(), ParseError> {
+        let (fields, amount) = fields(line, 2);
+        if fields.len() < 2 || amount.is_empty() {
+            return Err(self.malformed(line, "a species index, name and amount"));
+        }
+        let (number, name) = (fields[0], fields[1]);
+        let idx = self.names.len();
+        let name = match name.strip_prefix('$') {
+            Some(name) => {
+                self.boundary.push(idx);
+                name
+            }
+            None => name,
+        };
+        if self.species.insert(number, idx).is_some() || self.names.contains_right(name) {
+            return Err(ParseError::DuplicateDefinition {
+                name: name.to_string(),
+                location: Location::new(self.text, line.trim_start()),
+            });
+        }
+        let value = self.expr(amount)?.eval::<f64>(&[]).to_string();
+        self.amounts
+            .push(value.parse().map_err(|_| ParseError::InvalidNumber {
+                text: value,
+                location: Location::new(self.text, amount),
+            })?);
+        self.names.insert(idx, name.to_string());
+        Ok(())
+    }
+
+    /// Reads a comma-separated list of species numbers, where `0` means no species.
+    fn stoichiometry(&self, list: &'a str) -> Result<HashMap<usize, f64>, ParseError> {
+        let mut result = HashMap::new();
+        if list == "0" {
+            return Ok(result);
+        }
+        for number in list.split(',') {
+            let idx = self.species.get(number).ok_or_else(|| ParseError::Syntax {
+                expected: "the number of a declared species".to_string(),
+                location: Location::new(self.text, number),
+            })?;
+            *result.entry(*idx).or_insert(0.0) += 1.0;
+        }
+        Ok(result)
+    }
+
+    /// Reads a line of the `reactions` block: `index reactants products rate`.
+    fn reaction(&mut self, line: &'a str) -> Result<(), ParseError> {
+        let (fields, rate) = fields(line, 3);
+        if fields.len() < 3 || rate.is_empty() {
+            return Err(self.malformed(line, "a reaction index, reactants, products and rate"));
+        }
+        let reactants = self.stoichiometry(fields[1])?;
+        let products = self.stoichiometry(fields[2])?;
+        let mut rxn = Reaction::new(reactants, products, 1.0);
+        match self.expr(rate)? {
+            Expr::Param(name, value) => {
+                rxn.rate = value;
+                rxn.rate_param = Some(name);
+            }
+            expr => rxn.rate = expr.eval::<f64>(&[]),
+        }
+        self.rxns.push(rxn);
+        Ok(())
+    }
+}
+
+impl<T> Crn<T>
+where
+    T: Default + Clone + FromStr,
+{
+    /// Reads a CRN from a BioNetGen `.net` file, as written by `generate_network`.
+    ///
+    /// Species keep their BioNetGen names. Rates that are a single parameter stay linked to it, so [`Crn::set_parameter`] updates them; other rate expressions, such as `0.5*kf`, are evaluated once. Observables, groups and molecule types are ignored. Fixed species, whose names start with `$`, become boundary species without the `$`. Functions are not supported.
+    pub fn from_bionetgen(text: &str) -> Result<Crn<T>, ParseError> {
+        let mut importer = Importer {
+            text,
+            params: BTreeMap::new(),
+            species: HashMap::new(),
+            names: bimap::BiHashMap::new(),
+            amounts: Vec::new(),
+            rxns: Vec::new(),
+            boundary: Vec::new(),
+        };
+
+        let mut block: Option<&str> = None;
+        for line in text.lines() {
+            let line = match line.find('#') {
+                Some(comment) => &line[..comment],
+                None => line,
+            };
+            let (keyword, name) = fields(line, 1);
+            match (block, keyword.first().copied()) {
+                (_, None) => {}
+                (None, Some("begin")) => block = Some(name),
+                (Some(current), Some("end")) => {
+                    if name != current {
+                        return Err(importer.malformed(line, &format!("`end {current}`")));
+                    }
+                    block = None;
+                }
+                (None, Some(_)) => {}
+                (Some("parameters"), Some(_)) => importer.parameter(line)?,
+                (Some("species"), Some(_)) => importer.species(line)?,
+                (Some("reactions"), Some(_)) => importer.reaction(line)?,
+                (Some("functions"), Some(_)) => {
+                    return Err(ParseError::Unsupported {
+                        construct: "functions".to_string(),
+                        location: Location::new(text, line.trim_start()),
+                    })
+                }
+                (Some(_), Some(_)) => {}
+            }
+        }
+        if let Some(current) = block {
+            return Err(ParseError::Syntax {
+                expected: format!("`end {current}`"),
+                location: Location::new(text, &text[text.len()..]),
+            });
+        }
+
+        Ok(Crn::from_parts(
+            importer.rxns,
+            importer.amounts,
+            importer.names,
+            importer.params,
+            importer.boundary,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parse::ParseError, DetCrn, StoCrn};
+
+    /// A small network in the format written by BioNetGen.
+    const NET: &str = "# Created by BioNetGen 2.9.0
+begin parameters
+    1 kf      0.1  # Constant
+    2 kr      kf*2  # ConstantExpression
+    3 A0      100  # Constant
+end parameters
+begin species
+    1 A(b)        A0
+    2 B(a)        50
+    3 A(b!1).B(a!1) 0
+end species
+begin reactions
+    1 1,2 3 kf #_R1
+    2 3 1,2 kr #_reverse__R1
+    3 1,1 0 0.5*kf #_R2
+    4 0 2 1e-3 #_R3
+end reactions
+begin groups
+    1 Atot                 1,3
+end groups
+";
+
+    #[test]
+    fn import() {
+        let mut crn = StoCrn::from_bionetgen(NET).unwrap();
+        assert_eq!(crn.init_state.species, vec![100, 50, 0]);
+        assert_eq!(crn.names.get_by_left(&2).unwrap(), "A(b!1).B(a!1)");
+        assert_eq!(crn.params["kr"], 0.2);
+        assert_eq!(crn.rxns.len(), 4);
+        assert_eq!(crn.rxns[0].reactants.len(), 2);
+        assert_eq!(crn.rxns[2].reactants[&0], 2.0);
+        assert!(crn.rxns[2].products.is_empty());
+        assert_eq!(crn.rxns[2].rate, 0.05);
+        assert_eq!(crn.rxns[3].rate, 0.001);
+
+        crn.set_parameter("kf", 1.0).unwrap();
+        assert_eq!(crn.rxns[0].rate, 1.0);
+        crn.steps(10).unwrap();
+    }
+
+    #[test]
+    fn fixed() {
+        let net = "begin species\n  1 $S() 10\n  2 P() 0\nend species\nbegin reactions\n  1 1 2 1\nend reactions\n";
+        let mut crn = StoCrn::from_bionetgen(net).unwrap();
+        assert_eq!(crn.names.get_by_left(&0).unwrap(), "S()");
+        assert!(crn.boundary.contains(&0));
+        crn.steps(25).unwrap();
+        assert_eq!(crn.state.species, vec![10, 25]);
+    }
+
+    #[test]
+    fn errors() {
+        let err =
+            DetCrn::from_bionetgen("begin reactions\n  1 1 2 k\nend reactions\n").unwrap_err();
+        assert!(matches!(err, ParseError::Syntax { location, .. } if location.line == 2));
+        let err =
+            DetCrn::from_bionetgen("begin parameters\n  1 k 2*x\nend parameters\n").unwrap_err();
+        assert!(matches!(err, ParseError::UnknownParameter { name, .. } if name == "x"));
+        let err = DetCrn::from_bionetgen("begin species\n").unwrap_err();
+        assert!(matches!(err, ParseError::Syntax { expected, .. } if expected == "`end species`"));
+    }
+}
@@ -0,0 +1,156 @@
+use rand::Rng;
+use rand_distr::StandardNormal;
+
+use crate::{sto::seeded_rng, DetCrn, State};
+
+impl DetCrn {
+    /// Simulates a single timestep of the chemical Langevin equation with the Euler–Maruyama scheme. Each reaction changes the state by an explicit Euler step of its drift, its rate times `dt`, plus Gaussian noise with a variance of its rate times `dt`. This is first order, unlike the Runge–Kutta steps of [`DetCrn::step`].
+    ///
+    /// Rates are computed with negative amounts taken as zero, and negative rates, as a custom law can give, are taken as zero too, as in a stochastic simulation. Reactions with no rate add no noise, and amounts that the noise would make negative are set to zero. Fails, leaving the state as it was, if a rate is not finite.
+    pub fn langevin_step<R: Rng + ?Sized>(
+        &mut self,
+        dt: f64,
+        rng: &mut R,
+    ) -> Result<(), crate::Error> {
+        let clamped = State {
+            species: self.state.species.iter().map(|x| x.max(0.0)).collect(),
+            time: self.state.time,
+        };
+        let mut delta = vec![0.0; self.state.species.len()];
+        for rxn in &self.rxns {
+            let rate = clamped.rate(rxn);
+            if !rate.is_finite() {
+                return Err(crate::Error::InsufficientPrecision);
+            }
+            if rate <= 0.0 {
+                continue;
+            }
+            let noise: f64 = rng.sample(StandardNormal);
+            let firings = rate * dt + (rate * dt).sqrt() * noise;
+            for (species, change) in &rxn.delta {
+                delta[*species] += change * firings;
+            }
+        }
+        for (amount, change) in self.state.species.iter_mut().zip(delta) {
+            *amount = (*amount + change).max(0.0);
+        }
+        self.state.time += dt;
+        Ok(())
+    }
+
+    /// Simulates the chemical Langevin equation for a given amount of time, with a given timestep, drawing noise from a generator seeded with `seed`. The same seed gives the same trajectory. Returns the state after each step.
+    ///
+    /// This is a diffusion approximation between [`crate::StoCrn`] and [`DetCrn::simulate_history`]: it is close to the stochastic trajectory when every reaction fires many times per step.
+    pub fn simulate_langevin(
+        &mut self,
+        t: f64,
+        dt: f64,
+        seed: u64,
+    ) -> Result<Vec<State<f64>>, crate::Error> {
+        self.simulate_langevin_with_rng(t, dt, &mut seeded_rng(seed))
+    }
+
+    /// Simulates the chemical Langevin equation as [`DetCrn::simulate_langevin`] does, drawing noise from `rng`.
+    ///
+    /// A timed event happens at exactly its time, including at the very end: the step it falls in is split there, and the state is recorded just before and just after the event. The conditions of triggered events are checked after every step, and the state is recorded again after any that happen.
+    pub fn simulate_langevin_with_rng<R: Rng + ?Sized>(
+        &mut self,
+        t: f64,
+        dt: f64,
+        rng: &mut R,
+    ) -> Result<Vec<State<f64>>, crate::Error> {
+        let steps = (t / dt).ceil() as usize;
+        let stop = self.state.time + t;
+        let mut result: Vec<State<f64>> = Vec::with_capacity(steps);
+        let mut events = self.timed_events(self.state.time).into_iter().peekable();
+        let mut held = self.conditions();
+        for _ in 0..steps {
+            result.push(self.state.clone());
+            let end = self.state.time + dt;
+            while let Some(at) = events.peek().map(|(at, _)| *at).filter(|at| *at < end) {
+                if at > self.state.time {
+                    self.langevin_step(at - self.state.time, rng)?;
+                    self.state.time = at;
+                    result.push(self.state.clone());
+                }
+                while let Some((_, idx)) = events.next_if(|(time, _)| *time == at) {
+                    self.events[idx].apply(&mut self.state);
+                }
+                self.fire_triggered(&mut held);
+                result.push(self.state.clone());
+            }
+            self.langevin_step(end - self.state.time, rng)?;
+            self.state.time = end;
+            if self.fire_triggered(&mut held) {
+                result.push(self.state.clone());
+            }
+        }
+        // events at the end time happen too, even if rounding has left the time just short of it
+        let end = self.state.time.max(stop);
+        if events.peek().is_some_and(|(at, _)| *at <= end) {
+            result.push(self.state.clone());
+            while let Some((_, idx)) = events.next_if(|(at, _)| *at <= end) {
+                self.events[idx].apply(&mut self.state);
+            }
+            self.fire_triggered(&mut held);
+            result.push(self.state.clone());
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{sto::seeded_rng, DetCrn};
+
+    #[test]
+    fn statistics() {
+        // a birth–death process settles to a mean of 1000, with a standard deviation of about 32
+        let mut crn = DetCrn::parse("A = 1000; -> A : 1000; A -> : 1;").unwrap();
+        let history = crn.simulate_langevin(50.0, 0.01, 1).unwrap();
+        let settled: Vec<f64> = history[1000..].iter().map(|s| s.species[0]).collect();
+        let mean = settled.iter().sum::<f64>() / settled.len() as f64;
+        let variance =
+            settled.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / settled.len() as f64;
+        assert!((mean - 1000.0).abs() < 10.0, "mean {mean}");
+        assert!((variance - 1000.0).abs() < 300.0, "variance {variance}");
+
+        let again = DetCrn::parse("A = 1000; -> A : 1000; A -> : 1;")
+            .unwrap()
+            .simulate_langevin(50.0, 0.01, 1)
+            .unwrap();
+        assert_eq!(history, again);
+    }
+
+    #[test]
+    fn zero_boundary() {
+        let mut crn = DetCrn::parse("A = 3; $F = 2; A -> : 10; F -> F + B : 0.5;").unwrap();
+        let mut rng = seeded_rng(2);
+        for _ in 0..1000 {
+            crn.langevin_step(0.05, &mut rng).unwrap();
+            assert!(crn.state.species.iter().all(|x| *x >= 0.0));
+        }
+        assert_eq!(crn.state.species[0], 0.0);
+        assert_eq!(crn.state.species[1], 2.0);
+
+        // a negative rate is taken as zero, and one that is not finite is an error
+        let mut crn = DetCrn::parse("A = 3; -> B : -A;").unwrap();
+        crn.simulate_langevin(1.0, 0.01, 4).unwrap();
+        assert_eq!(crn.state.species, vec![3.0, 0.0]);
+        let mut crn = DetCrn::parse("A = 3; B = 0; -> C : A / B;").unwrap();
+        assert!(matches!(
+            crn.simulate_langevin(1.0, 0.01, 4),
+            Err(crate::Error::InsufficientPrecision)
+        ));
+        assert_eq!(crn.state.time, 0.0);
+    }
+
+    #[test]
+    fn events() {
+        let mut crn = DetCrn::parse("-> A : 100; at 2.5: B += 5; when A >= 300: A = 0;").unwrap();
+        let history = crn.simulate_langevin(5.0, 0.1, 3).unwrap();
+        assert!(history.iter().any(|state| state.time == 2.5));
+        assert_eq!(crn.state.species[1], 5.0);
+        assert!(history.iter().all(|state| state.species[0] < 320.0));
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::BTreeMap;
+
+use rand::Rng;
+
+use crate::sto::{waiting_time, Engine, Error, StoCrn};
+
+/// How many reactions happen between rebuilding the groups from scratch, so that rounding errors in their totals cannot build up.
+const REBUILD_INTERVAL: usize = 10_000;
+
+/// The binary exponent of a positive rate: the `g` with `2^g <= rate < 2^(g + 1)`. Subnormal rates share the lowest exponent, which still bounds them.
+fn exponent(rate: f64) -> i32 {
+    ((rate.to_bits() >> 52) & 0x7ff) as i32 - 1023
+}
+
+/// The reactions whose rates have the same binary exponent.
+#[derive(Default)]
+struct Group {
+    /// The reactions in the group, in no particular order.
+    members: Vec<usize>,
+    /// The sum of the members' rates, updated as they change.
+    total: f64,
+}
+
+/// The composition–rejection method of Slepoy, Thompson and Plimpton. Reactions are grouped by the power of two their rate falls between. The next reaction's group is chosen in proportion to the groups' total rates, and then a reaction within the group by rejection: a random member is accepted with probability its rate over the group's upper bound, which is at least a half. Both steps take about constant time however many reactions there are, as there are only as many groups as powers of two that the rates span.
+pub(crate) struct CompositionRejection {
+    /// The rate of each reaction in the current state.
+    rates: Vec<f64>,
+    /// The groups that have members, by exponent.
+    groups: BTreeMap<i32, Group>,
+    /// The exponent of each reaction's group and its position among the members, or none if its rate is zero.
+    location: Vec<Option<(i32, usize)>>,
+    /// The sum of all rates, updated as they change.
+    total: f64,
+    /// For each reaction, the reactions whose rates it can change.
+    dependents: Vec<Vec<usize>>,
+    /// Reactions since the groups were last rebuilt.
+    since_recompute: usize,
+}
+
+impl CompositionRejection {
+    /// Prepares to simulate `crn`.
+    pub(crate) fn new(crn: &StoCrn) -> Self {
+        let mut engine = Self {
+            rates: Vec::new(),
+            groups: BTreeMap::new(),
+            location: Vec::new(),
+            total: 0.0,
+            dependents: crn.dependents(),
+            since_recompute: 0,
+        };
+        engine.recompute(crn);
+        engine
+    }
+
+    /// Computes every rate, group and total from scratch.
+    fn recompute(&mut self, crn: &StoCrn) {
+        self.rates = vec![0.0; crn.rxns.len()];
+        self.groups.clear();
+        self.location = vec![None; crn.rxns.len()];
+        self.total = 0.0;
+        for (idx, rxn) in crn.rxns.iter().enumerate() {
+            self.set_rate(idx, crn.state.rate(rxn));
+        }
+        self.since_recompute = 0;
+    }
+
+    /// Changes the rate of a reaction, moving it to the group for its new rate.
+    fn set_rate(&mut self, rxn: usize, rate: f64) {
+        let old = std::mem::replace(&mut self.rates[rxn], rate);
+        self.total += rate - old;
+        let group = (rate > 0.0).then(|| exponent(rate));
+        match self.location[rxn] {
+            Some((exponent, _)) if Some(exponent) == group => {
+                self.groups.get_mut(&exponent).unwrap().total += rate - old;
+                return;
+            }
+            Some((exponent, pos)) => {
+                let from = self.groups.get_mut(&exponent).unwrap();
+                from.members.swap_remove(pos);
+                from.total -= old;
+                if let Some(moved) = from.members.get(pos) {
+                    self.location[*moved] = Some((exponent, pos));
+                }
+                if from.members.is_empty() {
+                    self.groups.remove(&exponent);
+                }
+            }
+            None => {}
+        }
+        self.location[rxn] = group.map(|exponent| {
+            let to = self.groups.entry(exponent).or_default();
+            to.members.push(rxn);
+            to.total += rate;
+            (exponent, to.members.len() - 1)
+        });
+    }
+
+    /// The exponent of the group chosen by `target`, a number between zero and the total rate. Groups with larger rates are searched first. None if rounding in the totals has left `target` past the last group.
+    fn choose_group(&self, mut target: f64) -> Option<i32> {
+        self.groups.iter().rev().find_map(|(exponent, group)| {
+            if target < group.total {
+                Some(*exponent)
+            } else {
+                target -= group.total;
+                None
+            }
+        })
+    }
+}
+
+impl Engine for CompositionRejection {
+    fn step_until<R: Rng + ?Sized>(
+        &mut self,
+        crn: &mut StoCrn,
+        limit: f64,
+        rng: &mut R,
+    ) -> Result<bool, Error> {
+        if self.total <= 0.0 || self.since_recompute >= REBUILD_INTERVAL {
+            // Rounding can leave the running total at or below zero while some rates are not.
+            self.recompute(crn);
+        }
+        let mut recomputed = false;
+        let (time, exponent) = loop {
+            if self.total <= 0.0 {
+                if limit == f64::INFINITY {
+                    return Err(Error::TerminalState);
+                }
+                crn.state.time = limit;
+                return Ok(false);
+            }
+
+            let time = crn.state.time + waiting_time(self.total, rng);
+            if time > limit {
+                crn.state.time = limit;
+                return Ok(false);
+            }
+            match self.choose_group(rng.gen::<f64>() * self.total) {
+                Some(exponent) => break (time, exponent),
+                // The totals are exact just after rebuilding, so a group can only be missed again if they are not finite.
+                None if recomputed => return Err(Error::InsufficientPrecision),
+                None => {
+                    // The running totals have drifted above the true ones; correct them and try again.
+                    self.recompute(crn);
+                    recomputed = true;
+                }
+            }
+        };
+        let group = &self.groups[&exponent];
+        let bound = 2f64.powi(exponent + 1);
+        let fired = loop {
+            let candidate = group.members[rng.gen_range(0..group.members.len())];
+            if rng.gen::<f64>() * bound < self.rates[candidate] {
+                break candidate;
+            }
+        };
+        crn.state.time = time;
+        crn.state.apply(&crn.rxns[fired]);
+        self.since_recompute += 1;
+
+        let dependents = std::mem::take(&mut self.dependents[fired]);
+        for &dependent in &dependents {
+            self.set_rate(dependent, crn.state.rate(&crn.rxns[dependent]));
+        }
+        self.dependents[fired] = dependents;
+        Ok(true)
+    }
+
+    fn refresh<R: Rng + ?Sized>(&mut self, crn: &StoCrn, _rng: &mut R) {
+        self.recompute(crn);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{exponent, CompositionRejection};
+    use crate::{
+        sto::{seeded_rng, Engine},
+        StoCrn,
+    };
+
+    #[test]
+    fn exponents() {
+        assert_eq!(exponent(1.0), 0);
+        assert_eq!(exponent(1.99), 0);
+        assert_eq!(exponent(2.0), 1);
+        assert_eq!(exponent(0.3), -2);
+        assert_eq!(exponent(1e300), 996);
+    }
+
+    #[test]
+    fn groups() {
+        let mut crn = StoCrn::parse(
+            "A = 20; B = 20; -> C : 1; A + B -> C : 100; C -> A + B : 10; A -> D : 0.01; D -> : 3;",
+        )
+        .unwrap();
+        let mut engine = CompositionRejection::new(&crn);
+        let mut rng = seeded_rng(10);
+        for _ in 0..500 {
+            engine
+                .step_until(&mut crn, f64::INFINITY, &mut rng)
+                .unwrap();
+            for (idx, rxn) in crn.rxns.iter().enumerate() {
+                let rate = crn.state.rate(rxn);
+                assert_eq!(engine.rates[idx], rate);
+                match engine.location[idx] {
+                    Some((group, pos)) => {
+                        assert_eq!(group, exponent(rate));
+                        assert_eq!(engine.groups[&group].members[pos], idx);
+                    }
+                    None => assert_eq!(rate, 0.0),
+                }
+            }
+            for group in engine.groups.values() {
+                let sum: f64 = group.members.iter().map(|idx| engine.rates[*idx]).sum();
+                approx::assert_relative_eq!(group.total, sum, max_relative = 1e-9);
+            }
+        }
+    }
+}
@@ -1,8 +1,11 @@
-use crate::{Crn, State};
+use crate::{event::Trigger, Crn, State};
 
 /// The maximum number of points to return from a simulation.
 const MAX_POINTS: usize = 100000;
 
+/// The number of halvings used to locate where an event's condition becomes true within a step.
+const BISECTIONS: usize = 50;
+
 /// A deterministic CRN. In a sense this is the "limiting" behavior of a stochastic CRN as the amounts of each species are scaled to infinity.
 pub type DetCrn = Crn<f64>;
 
@@ -40,13 +43,83 @@ impl DetCrn {
 }
 
 impl DetCrn {
+    /// Integrates up to time `end` in one step, unless the condition of an event that did not hold in `held` becomes true before then. Then it stops just after the condition becomes true, which is found by bisection, and returns true.
+    fn advance(&mut self, end: f64, held: &[bool]) -> bool {
+        let start = self.state.clone();
+        self.step(end - start.time);
+        self.state.time = end;
+        if !self.triggered(held) {
+            return false;
+        }
+        let (mut lo, mut hi) = (0.0, end - start.time);
+        for _ in 0..BISECTIONS {
+            let mid = (lo + hi) / 2.0;
+            self.state = start.clone();
+            self.step(mid);
+            if self.triggered(held) {
+                hi = mid;
+            } else {
+                lo = mid;
+            }
+        }
+        self.state = start.clone();
+        self.step(hi);
+        self.state.time = start.time + hi;
+        true
+    }
+
     /// Simulates for a given amount of time. Returns a collection of individual species' history.
+    ///
+    /// A timed event happens at exactly its time: the step it falls in is split there, and the state is recorded just before and just after the event. This includes events at the very end of the simulation. A triggered event happens where its condition becomes true, found by bisection within the step, and is recorded the same way. A condition that becomes true and false again within one step is missed.
     pub fn simulate_history(&mut self, t: f64, dt: f64) -> Result<Vec<State<f64>>, crate::Error> {
         let steps = (t / dt).ceil() as usize;
+        let stop = self.state.time + t;
         let mut result: Vec<State<f64>> = Vec::with_capacity(steps);
+        let mut events = self.timed_events(self.state.time).into_iter().peekable();
+        let mut held = self.conditions();
+        let triggers = self
+            .events
+            .iter()
+            .any(|event| matches!(event.trigger, Trigger::When(_)));
         for _ in 0..steps {
             result.push(self.state.clone());
-            self.step(dt);
+            let end = self.state.time + dt;
+            if !triggers && events.peek().is_none_or(|(at, _)| *at >= end) {
+                self.step(dt);
+                continue;
+            }
+            while self.state.time < end {
+                let before = self.state.time;
+                let next = events.peek().map(|(at, _)| *at).filter(|at| *at < end);
+                if self.advance(next.unwrap_or(end), &held) {
+                    result.push(self.state.clone());
+                    self.fire_triggered(&mut held);
+                    result.push(self.state.clone());
+                    continue;
+                }
+                self.fire_triggered(&mut held);
+                let Some(at) = next else {
+                    break;
+                };
+                if at > before {
+                    result.push(self.state.clone());
+                }
+                while let Some((_, idx)) = events.next_if(|(time, _)| *time == at) {
+                    self.events[idx].apply(&mut self.state);
+                }
+                self.fire_triggered(&mut held);
+                result.push(self.state.clone());
+            }
+        }
+        // events at the end time happen too, even if rounding has left the time just short of it
+        let end = self.state.time.max(stop);
+        if events.peek().is_some_and(|(at, _)| *at <= end) {
+            result.push(self.state.clone());
+            while let Some((_, idx)) = events.next_if(|(at, _)| *at <= end) {
+                self.events[idx].apply(&mut self.state);
+            }
+            self.fire_triggered(&mut held);
+            result.push(self.state.clone());
         }
         Ok(result)
     }
@@ -54,7 +127,7 @@ impl DetCrn {
 
 #[cfg(test)]
 mod tests {
-    use approx::{assert_relative_eq, assert_abs_diff_eq};
+    use approx::{assert_abs_diff_eq, assert_relative_eq};
 
     use crate::DetCrn;
 
@@ -89,4 +162,13 @@ mod tests {
         crn.simulate_history(T, 0.001).unwrap();
         assert_abs_diff_eq!(crn.state.species[1], 1.0 - (-T).exp(), epsilon = 0.001);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn boundary() {
+        const T: f64 = 10.0;
+        let mut crn = DetCrn::parse("$F = 2; F -> A;").unwrap();
+        crn.simulate_history(T, 0.001).unwrap();
+        assert_eq!(crn.state.species[0], 2.0);
+        assert_abs_diff_eq!(crn.state.species[1], 2.0 * T, epsilon = 0.001);
+    }
+}
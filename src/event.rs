@@ -0,0 +1,324 @@
+use std::ops::{Add, Sub};
+
+use crate::{expr::Expr, state::State, Crn};
+
+/// How the two sides of a [`Condition`] compare.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Comparison {
+    /// `<`
+    Less,
+    /// `<=`
+    LessEqual,
+    /// `>`
+    Greater,
+    /// `>=`
+    GreaterEqual,
+}
+
+impl Comparison {
+    /// The comparison's symbol, such as `>=`.
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Comparison::Less => "<",
+            Comparison::LessEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterEqual => ">=",
+        }
+    }
+
+    /// The comparison with the given symbol.
+    pub fn from_symbol(symbol: &str) -> Option<Self> {
+        match symbol {
+            "<" => Some(Comparison::Less),
+            "<=" => Some(Comparison::LessEqual),
+            ">" => Some(Comparison::Greater),
+            ">=" => Some(Comparison::GreaterEqual),
+            _ => None,
+        }
+    }
+}
+
+/// A comparison of two expressions over species and parameters, such as `A > 50` or `A + B <= 2 * N`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Condition {
+    /// The left-hand side.
+    pub lhs: Expr,
+    /// How the sides compare.
+    pub comparison: Comparison,
+    /// The right-hand side.
+    pub rhs: Expr,
+}
+
+impl Condition {
+    /// Whether the condition holds for the given species amounts.
+    pub fn holds<T: Copy + Into<f64>>(&self, species: &[T]) -> bool {
+        let (lhs, rhs) = (self.lhs.eval(species), self.rhs.eval(species));
+        match self.comparison {
+            Comparison::Less => lhs < rhs,
+            Comparison::LessEqual => lhs <= rhs,
+            Comparison::Greater => lhs > rhs,
+            Comparison::GreaterEqual => lhs >= rhs,
+        }
+    }
+}
+
+/// When an event happens.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Trigger {
+    /// At a fixed time, written `at 5:`.
+    At(f64),
+    /// Whenever the condition becomes true, written `when A > 50:`. A condition that already holds when a simulation starts has not become true, so it must become false before the event can happen.
+    When(Condition),
+}
+
+/// A change to the amount of a species.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Change<T> {
+    /// Set the species' amount, written `A = 0`.
+    Set(usize, T),
+    /// Add to the species' amount, written `A += 100`.
+    Add(usize, T),
+    /// Subtract from the species' amount, stopping at zero. Written `A -= 10`.
+    Sub(usize, T),
+}
+
+impl<T> Change<T> {
+    /// The index of the species that changes.
+    pub fn species(&self) -> usize {
+        match self {
+            Change::Set(species, _) | Change::Add(species, _) | Change::Sub(species, _) => *species,
+        }
+    }
+}
+
+/// A change to several species at once, such as `at 5: A += 100, B = 0;`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Event<T> {
+    /// When the event happens.
+    pub trigger: Trigger,
+    /// The changes it makes, in order.
+    pub changes: Vec<Change<T>>,
+}
+
+impl<T> Event<T>
+where
+    T: Copy + Default + PartialOrd + Add<Output = T> + Sub<Output = T>,
+{
+    /// Makes the event's changes to `state`.
+    pub fn apply(&self, state: &mut State<T>) {
+        for change in &self.changes {
+            let amount = &mut state.species[change.species()];
+            *amount = match change {
+                Change::Set(_, value) => *value,
+                Change::Add(_, value) => *amount + *value,
+                Change::Sub(_, value) if *value < *amount => *amount - *value,
+                Change::Sub(..) => T::default(),
+            };
+        }
+    }
+}
+
+impl<T> Crn<T> {
+    /// The times of the timed events at or after `time`, in order, with the index of each event. Events at the same time keep the order they were defined in.
+    pub(crate) fn timed_events(&self, time: f64) -> Vec<(f64, usize)> {
+        let mut events: Vec<_> = self
+            .events
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, event)| match event.trigger {
+                Trigger::At(at) if at >= time => Some((at, idx)),
+                _ => None,
+            })
+            .collect();
+        events.sort_by(|a, b| a.0.total_cmp(&b.0));
+        events
+    }
+}
+
+impl<T> Crn<T>
+where
+    T: Copy + Default + PartialOrd + Add<Output = T> + Sub<Output = T> + Into<f64>,
+{
+    /// Whether the condition of each event holds in the current state. Timed events have no condition, and never hold.
+    pub(crate) fn conditions(&self) -> Vec<bool> {
+        self.events
+            .iter()
+            .map(|event| match &event.trigger {
+                Trigger::When(condition) => condition.holds(&self.state.species),
+                Trigger::At(_) => false,
+            })
+            .collect()
+    }
+
+    /// Whether the condition of an event that did not hold in `held` holds now.
+    pub(crate) fn triggered(&self, held: &[bool]) -> bool {
+        self.events
+            .iter()
+            .zip(held)
+            .any(|(event, held)| match &event.trigger {
+                Trigger::When(condition) => !held && condition.holds(&self.state.species),
+                Trigger::At(_) => false,
+            })
+    }
+
+    /// Makes the changes of the events whose conditions have become true since they were recorded in `held`, in order, then records the current conditions. An event can trigger others; each happens at most once per call. Returns whether any happened.
+    pub(crate) fn fire_triggered(&mut self, held: &mut [bool]) -> bool {
+        let mut fired = false;
+        loop {
+            let mut any = false;
+            for (event, held) in self.events.iter().zip(held.iter_mut()) {
+                let Trigger::When(condition) = &event.trigger else {
+                    continue;
+                };
+                if !*held && condition.holds(&self.state.species) {
+                    event.apply(&mut self.state);
+                    *held = true;
+                    any = true;
+                }
+            }
+            if !any {
+                break;
+            }
+            fired = true;
+        }
+        held.copy_from_slice(&self.conditions());
+        fired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Change, Trigger};
+    use crate::{DetCrn, ParseError, StoCrn};
+
+    #[test]
+    fn parse() {
+        let crn = StoCrn::parse("A -> B; at 1e-3: A += 100, B = 0; at 5: B -= 2;").unwrap();
+        assert_eq!(crn.events.len(), 2);
+        assert_eq!(crn.events[0].trigger, Trigger::At(0.001));
+        assert_eq!(
+            crn.events[0].changes,
+            vec![Change::Add(0, 100), Change::Set(1, 0)]
+        );
+        let reparsed = StoCrn::parse(&crn.to_string()).unwrap();
+        assert_eq!(reparsed.events, crn.events);
+
+        assert!(matches!(
+            StoCrn::parse("at 5: A += 0.5;"),
+            Err(ParseError::InvalidNumber { text, .. }) if text == "0.5"
+        ));
+        assert!(matches!(
+            StoCrn::parse("at 5: A + 1;"),
+            Err(ParseError::Syntax { expected, .. }) if expected == "`=`, `+=` or `-=`"
+        ));
+        // `at` is still a species name.
+        assert!(StoCrn::parse("at = 2; at -> B;").is_ok());
+    }
+
+    #[test]
+    fn stochastic() {
+        let mut crn =
+            StoCrn::parse("A = 0; B = 50; at 2: A += 100, B = 0; at 4.5: A -= 1000;").unwrap();
+        let history = crn.simulate_history(10.0).unwrap();
+        assert_eq!(history.len(), 4);
+        assert_eq!(history[1].time, 2.0);
+        assert_eq!(history[1].species, vec![100.0, 0.0]);
+        assert_eq!(history[3].time, 4.5);
+        assert_eq!(history[3].species, vec![0.0, 0.0]);
+
+        // Reactions continue from the exact event time, and events fire once.
+        let mut crn = StoCrn::parse("A -> B : 10; at 1: A += 20; at 3: A += 5;").unwrap();
+        let first = crn.simulate_history(2.0).unwrap();
+        assert_eq!(first[0].time, 1.0);
+        assert_eq!(first[1].species, vec![20.0, 0.0]);
+        assert!(first[2..].iter().all(|state| state.time > 1.0));
+        let second = crn.simulate_history(10.0).unwrap();
+        assert_eq!(second.iter().filter(|state| state.time == 3.0).count(), 2);
+        assert_eq!(crn.state.species, vec![0, 25]);
+    }
+
+    #[test]
+    fn deterministic() {
+        let mut crn = DetCrn::parse("A = 1; A -> : 1; at 0.25: A += 1; at 0.5: A = 0;").unwrap();
+        let history = crn.simulate_history(1.0, 0.1).unwrap();
+        let at = |time: f64| history.iter().position(|state| state.time == time).unwrap();
+        let before = &history[at(0.25)];
+        let after = &history[at(0.25) + 1];
+        assert_eq!(after.time, 0.25);
+        approx::assert_relative_eq!(before.species[0], (-0.25f64).exp(), max_relative = 1e-6);
+        approx::assert_relative_eq!(after.species[0], before.species[0] + 1.0);
+        assert_eq!(history[at(0.5) + 1].species[0], 0.0);
+        assert_eq!(crn.state.species[0], 0.0);
+    }
+
+    #[test]
+    fn at_end() {
+        // an event at exactly the end time happens in every simulator, and one after it does not
+        let text = "A = 1; A -> : 0.1; at 1: B += 5; at 1.5: B += 5;";
+        let mut crn = StoCrn::parse(text).unwrap();
+        let history = crn.simulate_history(1.0).unwrap();
+        assert_eq!(crn.state.species[1], 5);
+        assert_eq!(history.last().unwrap().species[1], 5.0);
+
+        let mut crn = DetCrn::parse(text).unwrap();
+        let history = crn.simulate_history(1.0, 0.1).unwrap();
+        assert_eq!(crn.state.species[1], 5.0);
+        assert_eq!(history.last().unwrap().species[1], 5.0);
+        crn.reset();
+        let history = crn.simulate_langevin(1.0, 0.1, 1).unwrap();
+        assert_eq!(crn.state.species[1], 5.0);
+        assert_eq!(history.last().unwrap().species[1], 5.0);
+    }
+
+    #[test]
+    fn triggered() {
+        let mut crn =
+            StoCrn::parse("param N = 25; -> A : 100; when A + C >= 2 * N: A = 0, C += 1;").unwrap();
+        assert_eq!(
+            crn.to_string().lines().last(),
+            Some("when A + C >= 2 * N: A = 0, C += 1;")
+        );
+        let reparsed = StoCrn::parse(&crn.to_string()).unwrap();
+        assert_eq!(reparsed.events, crn.events);
+        let history = crn.simulate_history(5.0).unwrap();
+        assert!(history
+            .iter()
+            .all(|state| state.species[0] + state.species[1] <= 50.0));
+        assert!(crn.state.species[1] > 0);
+        crn.set_parameter("N", 1000.0).unwrap();
+        crn.reset();
+        crn.simulate_history(5.0).unwrap();
+        assert_eq!(crn.state.species[1], 0);
+
+        assert!(matches!(
+            StoCrn::parse("when X > 1: A = 0;"),
+            Err(ParseError::UnknownParameter { name, .. }) if name == "X"
+        ));
+        assert!(StoCrn::parse("when = 1; when -> A;").is_ok());
+    }
+
+    #[test]
+    fn crossing() {
+        let mut crn = DetCrn::parse("-> A : 1; when A > 2.5: A = 0, B += 1;").unwrap();
+        let history = crn.simulate_history(10.0, 0.1).unwrap();
+        assert_eq!(crn.state.species[1], 3.0);
+        let fired = history
+            .iter()
+            .position(|state| state.species[1] == 1.0)
+            .unwrap();
+        approx::assert_abs_diff_eq!(history[fired].time, 2.5, epsilon = 1e-9);
+        approx::assert_abs_diff_eq!(history[fired - 1].species[0], 2.5, epsilon = 1e-9);
+        assert_eq!(history[fired].species[0], 0.0);
+
+        // A condition that holds at the start has not become true.
+        let mut crn =
+            DetCrn::parse("A = 100; A -> : 1; when A > 50: B += 1; when A < 50: C += 1;").unwrap();
+        crn.simulate_history(2.0, 0.01).unwrap();
+        assert_eq!(crn.state.species[1..], [0.0, 1.0]);
+    }
+}
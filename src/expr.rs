@@ -0,0 +1,402 @@
+use std::collections::{BTreeSet, HashMap};
+
+/// Writes a number in its shortest form that parses back to exactly the same value. Very large and very small magnitudes use scientific notation, such as `1e-9`.
+pub(crate) fn format_number(x: f64) -> String {
+    let debug = format!("{x:?}");
+    match debug.strip_suffix(".0") {
+        Some(integer) => integer.to_string(),
+        None => debug,
+    }
+}
+
+/// A binary arithmetic operator.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BinOp {
+    /// Addition.
+    Add,
+    /// Subtraction.
+    Sub,
+    /// Multiplication.
+    Mul,
+    /// Division.
+    Div,
+    /// Exponentiation.
+    Pow,
+}
+
+impl BinOp {
+    /// The operator as written in a CRN definition.
+    fn symbol(self) -> &'static str {
+        match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Pow => "^",
+        }
+    }
+
+    /// Binding strength of the operator. Higher binds tighter.
+    fn precedence(self) -> u8 {
+        match self {
+            BinOp::Add | BinOp::Sub => 1,
+            BinOp::Mul | BinOp::Div => 2,
+            BinOp::Pow => 4,
+        }
+    }
+}
+
+/// A built-in function that can appear in a rate expression.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Func {
+    /// `mm(s, vmax, km)`: Michaelis–Menten kinetics, `vmax * s / (km + s)`.
+    MichaelisMenten,
+    /// `hill(s, k, n)`: activating Hill function, `s^n / (k^n + s^n)`.
+    Hill,
+    /// `hillr(s, k, n)`: repressing Hill function, `k^n / (k^n + s^n)`.
+    HillRepression,
+    /// `exp(x)`: the exponential function.
+    Exp,
+    /// `ln(x)`: the natural logarithm.
+    Ln,
+}
+
+impl Func {
+    /// Every built-in function.
+    pub const ALL: [Func; 5] = [
+        Func::MichaelisMenten,
+        Func::Hill,
+        Func::HillRepression,
+        Func::Exp,
+        Func::Ln,
+    ];
+
+    /// The function's name in a CRN definition.
+    pub fn name(self) -> &'static str {
+        match self {
+            Func::MichaelisMenten => "mm",
+            Func::Hill => "hill",
+            Func::HillRepression => "hillr",
+            Func::Exp => "exp",
+            Func::Ln => "ln",
+        }
+    }
+
+    /// Looks up a function by its name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|f| f.name() == name)
+    }
+
+    /// The number of arguments the function takes.
+    pub fn arity(self) -> usize {
+        match self {
+            Func::MichaelisMenten | Func::Hill | Func::HillRepression => 3,
+            Func::Exp | Func::Ln => 1,
+        }
+    }
+
+    /// Applies the function to already evaluated arguments.
+    fn apply(self, args: &[f64]) -> f64 {
+        match self {
+            Func::MichaelisMenten => args[1] * args[0] / (args[2] + args[0]),
+            Func::Hill => {
+                let (s, k) = (args[0].powf(args[2]), args[1].powf(args[2]));
+                s / (k + s)
+            }
+            Func::HillRepression => {
+                let (s, k) = (args[0].powf(args[2]), args[1].powf(args[2]));
+                k / (k + s)
+            }
+            Func::Exp => args[0].exp(),
+            Func::Ln => args[0].ln(),
+        }
+    }
+}
+
+/// An arithmetic expression over species amounts and parameters.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Expr {
+    /// A constant.
+    Num(f64),
+    /// The amount of the species with this index.
+    Species(usize),
+    /// A named parameter and its current value.
+    Param(String, f64),
+    /// Negation.
+    Neg(Box<Expr>),
+    /// A binary operation.
+    BinOp(BinOp, Box<Expr>, Box<Expr>),
+    /// A call to a built-in function.
+    Call(Func, Vec<Expr>),
+}
+
+impl Expr {
+    /// Evaluates the expression, reading species amounts from `species`.
+    pub fn eval<T: Copy + Into<f64>>(&self, species: &[T]) -> f64 {
+        match self {
+            Expr::Num(x) => *x,
+            Expr::Species(i) => species[*i].into(),
+            Expr::Param(_, value) => *value,
+            Expr::Neg(e) => -e.eval(species),
+            Expr::BinOp(op, a, b) => {
+                let (a, b) = (a.eval(species), b.eval(species));
+                match op {
+                    BinOp::Add => a + b,
+                    BinOp::Sub => a - b,
+                    BinOp::Mul => a * b,
+                    BinOp::Div => a / b,
+                    BinOp::Pow => a.powf(b),
+                }
+            }
+            Expr::Call(f, args) => {
+                let args: Vec<f64> = args.iter().map(|arg| arg.eval(species)).collect();
+                f.apply(&args)
+            }
+        }
+    }
+
+    /// The indices of the species the expression refers to.
+    pub fn species(&self) -> BTreeSet<usize> {
+        match self {
+            Expr::Num(_) | Expr::Param(..) => BTreeSet::new(),
+            Expr::Species(i) => BTreeSet::from([*i]),
+            Expr::Neg(e) => e.species(),
+            Expr::BinOp(_, a, b) => &a.species() | &b.species(),
+            Expr::Call(_, args) => args.iter().flat_map(Expr::species).collect(),
+        }
+    }
+
+    /// Updates the value of every reference to parameter `name`. Returns true if there were any.
+    pub fn set_param(&mut self, name: &str, value: f64) -> bool {
+        match self {
+            Expr::Num(_) | Expr::Species(_) => false,
+            Expr::Param(param, v) => {
+                if param == name {
+                    *v = value;
+                }
+                param == name
+            }
+            Expr::Neg(e) => e.set_param(name, value),
+            Expr::BinOp(_, a, b) => a.set_param(name, value) | b.set_param(name, value),
+            Expr::Call(_, args) => args
+                .iter_mut()
+                .fold(false, |found, arg| arg.set_param(name, value) | found),
+        }
+    }
+
+    /// Appends the factors of a product to `factors`.
+    fn factors<'a>(&'a self, factors: &mut Vec<&'a Expr>) {
+        match self {
+            Expr::BinOp(BinOp::Mul, a, b) => {
+                a.factors(factors);
+                b.factors(factors);
+            }
+            e => factors.push(e),
+        }
+    }
+
+    /// Checks whether the expression is a mass-action rate law for `reactants`: a product of each reactant raised to its coefficient and either a single parameter or only numbers.
+    ///
+    /// Returns the rate parameter, and the name of the parameter it comes from if there is one. A parameter combined with anything else, as in `2 * k * A`, is not mass-action, so that the rate still follows the parameter when it changes.
+    pub fn as_mass_action(&self, reactants: &HashMap<usize, f64>) -> Option<(f64, Option<String>)> {
+        let mut factors = Vec::new();
+        self.factors(&mut factors);
+        let mut constant = 1.0;
+        let mut params = Vec::new();
+        let mut powers = HashMap::new();
+        for factor in factors {
+            match factor {
+                Expr::Num(x) => constant *= x,
+                Expr::Param(name, value) => params.push((name, value)),
+                Expr::Species(i) => *powers.entry(*i).or_insert(0.0) += 1.0,
+                Expr::BinOp(BinOp::Pow, base, exponent) => match (&**base, &**exponent) {
+                    (Expr::Species(i), Expr::Num(n)) if *n > 0.0 => {
+                        *powers.entry(*i).or_insert(0.0) += n
+                    }
+                    _ => return None,
+                },
+                _ => return None,
+            }
+        }
+        if powers != *reactants {
+            return None;
+        }
+        match params[..] {
+            [] => Some((constant, None)),
+            [(name, value)] if constant == 1.0 => Some((*value, Some(name.clone()))),
+            _ => None,
+        }
+    }
+
+    /// Rewrites calls to `mm`, `hill` and `hillr` as plain arithmetic, for formats that lack those functions.
+    pub fn expand(&self) -> Expr {
+        let bin = |op, a: Expr, b: Expr| Expr::BinOp(op, Box::new(a), Box::new(b));
+        match self {
+            Expr::Num(_) | Expr::Species(_) | Expr::Param(..) => self.clone(),
+            Expr::Neg(e) => Expr::Neg(Box::new(e.expand())),
+            Expr::BinOp(op, a, b) => bin(*op, a.expand(), b.expand()),
+            Expr::Call(f, args) => {
+                let args: Vec<Expr> = args.iter().map(Expr::expand).collect();
+                match f {
+                    Func::MichaelisMenten => {
+                        let [s, vmax, km] = <[Expr; 3]>::try_from(args).unwrap();
+                        bin(
+                            BinOp::Div,
+                            bin(BinOp::Mul, vmax, s.clone()),
+                            bin(BinOp::Add, km, s),
+                        )
+                    }
+                    Func::Hill | Func::HillRepression => {
+                        let [s, k, n] = <[Expr; 3]>::try_from(args).unwrap();
+                        let s = bin(BinOp::Pow, s, n.clone());
+                        let k = bin(BinOp::Pow, k, n);
+                        let numerator = if *f == Func::Hill {
+                            s.clone()
+                        } else {
+                            k.clone()
+                        };
+                        bin(BinOp::Div, numerator, bin(BinOp::Add, k, s))
+                    }
+                    Func::Exp | Func::Ln => Expr::Call(*f, args),
+                }
+            }
+        }
+    }
+
+    /// Binding strength of the expression's outermost operation. Higher binds tighter.
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Num(x) if x.is_sign_negative() => 3,
+            Expr::Neg(_) => 3,
+            Expr::BinOp(op, ..) => op.precedence(),
+            _ => 5,
+        }
+    }
+
+    /// Writes the expression in CRN syntax, naming species with `species_name`.
+    pub fn format(&self, species_name: &dyn Fn(usize) -> String) -> String {
+        self.format_with(species_name, &|name, _| {
+            crate::parse::format_name(name).into_owned()
+        })
+    }
+
+    /// Writes the expression in CRN syntax, naming species with `species_name` and parameters, given their name and value, with `param_name`.
+    pub(crate) fn format_with(
+        &self,
+        species_name: &dyn Fn(usize) -> String,
+        param_name: &dyn Fn(&str, f64) -> String,
+    ) -> String {
+        let operand = |e: &Expr, min: u8| {
+            if e.precedence() < min {
+                format!("({})", e.format_with(species_name, param_name))
+            } else {
+                e.format_with(species_name, param_name)
+            }
+        };
+
+        match self {
+            Expr::Num(x) => format_number(*x),
+            Expr::Species(i) => species_name(*i),
+            Expr::Param(name, value) => param_name(name, *value),
+            Expr::Neg(e) => format!("-{}", operand(e, 3)),
+            Expr::BinOp(op, a, b) => {
+                let (left, right) = match op {
+                    BinOp::Pow => (5, 3),
+                    _ => (op.precedence(), op.precedence() + 1),
+                };
+                let sep = if *op == BinOp::Pow { "" } else { " " };
+                format!(
+                    "{}{sep}{}{sep}{}",
+                    operand(a, left),
+                    op.symbol(),
+                    operand(b, right)
+                )
+            }
+            Expr::Call(f, args) => format!(
+                "{}({})",
+                f.name(),
+                args.iter()
+                    .map(|arg| arg.format_with(species_name, param_name))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{BinOp, Expr, Func};
+
+    #[test]
+    fn eval() {
+        let hill = Expr::Call(
+            Func::Hill,
+            vec![Expr::Species(0), Expr::Num(10.0), Expr::Num(2.0)],
+        );
+        assert_eq!(hill.eval(&[10]), 0.5);
+        let mm = Expr::Call(
+            Func::MichaelisMenten,
+            vec![
+                Expr::Species(1),
+                Expr::Param("v".to_string(), 4.0),
+                Expr::Num(1.0),
+            ],
+        );
+        assert_eq!(mm.eval(&[0.0, 1.0]), 2.0);
+    }
+
+    #[test]
+    fn format_parenthesizes() {
+        let name = |i: usize| ["a", "b"][i].to_string();
+        let e = Expr::BinOp(
+            BinOp::Mul,
+            Box::new(Expr::BinOp(
+                BinOp::Sub,
+                Box::new(Expr::Species(0)),
+                Box::new(Expr::Species(1)),
+            )),
+            Box::new(Expr::BinOp(
+                BinOp::Pow,
+                Box::new(Expr::Num(-2.0)),
+                Box::new(Expr::Neg(Box::new(Expr::Species(1)))),
+            )),
+        );
+        assert_eq!(e.format(&name), "(a - b) * (-2)^-b");
+    }
+
+    #[test]
+    fn mass_action() {
+        let k = || Box::new(Expr::Param("k".to_string(), 0.5));
+        let a2 = || {
+            Box::new(Expr::BinOp(
+                BinOp::Pow,
+                Box::new(Expr::Species(0)),
+                Box::new(Expr::Num(2.0)),
+            ))
+        };
+        let reactants = HashMap::from([(0, 2.0), (1, 1.0)]);
+        let law = Expr::BinOp(
+            BinOp::Mul,
+            Box::new(Expr::BinOp(BinOp::Mul, k(), a2())),
+            Box::new(Expr::Species(1)),
+        );
+        assert_eq!(
+            law.as_mass_action(&reactants),
+            Some((0.5, Some("k".to_string())))
+        );
+        let law = Expr::BinOp(BinOp::Mul, Box::new(law), Box::new(Expr::Num(2.0)));
+        assert_eq!(law.as_mass_action(&reactants), None);
+        let law = Expr::BinOp(BinOp::Mul, a2(), Box::new(Expr::Species(1)));
+        let law = Expr::BinOp(BinOp::Mul, Box::new(law), Box::new(Expr::Num(2.0)));
+        assert_eq!(law.as_mass_action(&reactants), Some((2.0, None)));
+        assert_eq!(law.as_mass_action(&HashMap::from([(0, 2.0)])), None);
+        let law = Expr::BinOp(BinOp::Add, k(), a2());
+        assert_eq!(law.as_mass_action(&reactants), None);
+    }
+}
@@ -4,43 +4,92 @@
 #![warn(missing_docs)]
 #![warn(clippy::missing_docs_in_private_items)]
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt::Display;
 use std::str::FromStr;
 
 pub use det::DetCrn;
+use event::{Change, Event, Trigger};
+use expr::{BinOp, Expr};
 use itertools::Itertools;
+pub use parse::ParseError;
 pub use state::State;
 pub use sto::Error;
 pub use sto::StoCrn;
 
+/// Reading and writing Antimony models.
+pub mod antimony;
+/// Reading BioNetGen `.net` files.
+pub mod bng;
+/// The chemical Langevin equation for deterministic CRNs.
+mod cle;
+/// The composition–rejection method for stochastic CRNs.
+mod cr;
 /// Deterministic CRNs.
 pub mod det;
+/// Events that change species amounts during a simulation.
+pub mod event;
+/// Arithmetic expressions for rate laws.
+pub mod expr;
+/// Reading CRNs from files and readers in any supported format.
+pub mod load;
+/// Gibson and Bruck's next reaction method for stochastic CRNs.
+mod nrm;
+/// The optimized direct method for stochastic CRNs.
+mod odm;
 /// Parsing CRNs from strings.
 pub mod parse;
 /// Some fun CRNs to play with.
 pub mod presets;
+/// Reading and writing SBML models.
+pub mod sbml;
+/// Serialized forms for fields whose types have no stable one.
+#[cfg(feature = "serde")]
+mod serde_fields;
 /// State of a CRN.
 pub mod state;
 /// Stochastic CRNs.
 pub mod sto;
+/// Adaptive tau-leaping for stochastic CRNs.
+mod tau;
+
+/// How the rate of a reaction depends on the state of the CRN.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum KineticLaw {
+    /// The rate parameter times the amount of each reactant, raised to its stoichiometric coefficient. Stochastic CRNs count distinct combinations of molecules instead.
+    #[default]
+    MassAction,
+    /// The rate is given directly by an expression, and the rate parameter is ignored.
+    Custom(Expr),
+}
 
 /// A chemical reaction, with a rate parameter.
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Reaction {
-    /// Reactants and their stoichiometric coefficients.
-    pub reactants: HashMap<usize, i32>,
+    /// Reactants and their stoichiometric coefficients. Coefficients are whole numbers except in deterministic CRNs.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_fields::sorted_map"))]
+    pub reactants: HashMap<usize, f64>,
     /// Products and their stoichiometric coefficients.
-    pub products: HashMap<usize, i32>,
-    /// The change in a species' amount when this reaction occurs.
-    pub delta: HashMap<usize, i32>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_fields::sorted_map"))]
+    pub products: HashMap<usize, f64>,
+    /// The change in a species' amount when this reaction occurs. Boundary species are left out.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_fields::sorted_map"))]
+    pub delta: HashMap<usize, f64>,
     /// The rate parameter of this reaction.
     pub rate: f64,
+    /// Name of the parameter that sets this reaction's rate, if any.
+    pub rate_param: Option<String>,
+    /// How the reaction's rate depends on the state.
+    pub law: KineticLaw,
+    /// Index of the reaction in the same CRN that undoes this one, if the pair was declared with `<->`.
+    pub reverse: Option<usize>,
 }
 
 impl Reaction {
     /// Create a new reaction from reactants, products, and a rate parameter.
-    pub fn new(reactants: HashMap<usize, i32>, products: HashMap<usize, i32>, rate: f64) -> Self {
+    pub fn new(reactants: HashMap<usize, f64>, products: HashMap<usize, f64>, rate: f64) -> Self {
         Self {
             reactants: reactants.clone(),
             delta: {
@@ -57,12 +106,109 @@ impl Reaction {
             },
             products,
             rate,
+            rate_param: None,
+            law: KineticLaw::MassAction,
+            reverse: None,
+        }
+    }
+
+    /// Create a new reaction whose rate is given by `law`. A law of mass-action form, such as `k * A * B^2` for the reactants `A + 2B`, gives an ordinary mass-action reaction.
+    pub fn with_law(
+        reactants: HashMap<usize, f64>,
+        products: HashMap<usize, f64>,
+        law: Expr,
+    ) -> Self {
+        match law.as_mass_action(&reactants) {
+            Some((rate, rate_param)) => {
+                let mut rxn = Self::new(reactants, products, rate);
+                rxn.rate_param = rate_param;
+                rxn
+            }
+            None => {
+                let mut rxn = Self::new(reactants, products, 1.0);
+                rxn.law = KineticLaw::Custom(law);
+                rxn
+            }
+        }
+    }
+
+    /// Splits a rate law of the form `forward - reverse`, where both terms are mass-action, into a forward and a reverse reaction. Their `reverse` indices are left for the caller to set.
+    pub(crate) fn pair_from_law(
+        law: &Expr,
+        reactants: &HashMap<usize, f64>,
+        products: &HashMap<usize, f64>,
+    ) -> Option<[Reaction; 2]> {
+        let Expr::BinOp(BinOp::Sub, forward, reverse) = law else {
+            return None;
+        };
+        let forward = Reaction::with_law(reactants.clone(), products.clone(), (**forward).clone());
+        let reverse = Reaction::with_law(products.clone(), reactants.clone(), (**reverse).clone());
+        (forward.law == KineticLaw::MassAction && reverse.law == KineticLaw::MassAction)
+            .then_some([forward, reverse])
+    }
+
+    /// The reaction's rate law written out as an expression, such as `k * A * B^2` for a mass-action reaction.
+    pub fn rate_law(&self) -> Expr {
+        let rate = match (&self.law, &self.rate_param) {
+            (KineticLaw::Custom(law), _) => return law.clone(),
+            (KineticLaw::MassAction, Some(name)) => Expr::Param(name.clone(), self.rate),
+            (KineticLaw::MassAction, None) => Expr::Num(self.rate),
+        };
+        let reactants = self
+            .reactants
+            .iter()
+            .sorted_by_key(|(species, _)| **species);
+        reactants.fold(rate, |acc, (species, count)| {
+            let factor = if *count == 1.0 {
+                Expr::Species(*species)
+            } else {
+                Expr::BinOp(
+                    BinOp::Pow,
+                    Box::new(Expr::Species(*species)),
+                    Box::new(Expr::Num(*count)),
+                )
+            };
+            Expr::BinOp(BinOp::Mul, Box::new(acc), Box::new(factor))
+        })
+    }
+
+    /// The species whose amounts the reaction's rate depends on: its reactants, and any species in a custom rate law.
+    pub fn rate_species(&self) -> BTreeSet<usize> {
+        let mut species: BTreeSet<usize> = self.reactants.keys().copied().collect();
+        if let KineticLaw::Custom(law) = &self.law {
+            species.extend(law.species());
         }
+        species
     }
 }
 
+/// Setting a parameter can fail if the CRN has no parameter with that name, or if an initial amount computed from it is invalid.
+#[derive(thiserror::Error, Debug)]
+pub enum ParamError {
+    /// The CRN has no parameter with this name.
+    #[error("Unknown parameter `{0}`")]
+    UnknownParameter(String),
+    /// An initial amount computed from parameters cannot be used for this type of CRN, such as a fraction in a stochastic CRN.
+    #[error("Invalid initial amount {1} for species `{0}`")]
+    InvalidAmount(String, f64),
+}
+
+/// Writing a CRN in another format can fail if the CRN uses something the format cannot express.
+#[derive(thiserror::Error, Debug)]
+pub enum ExportError {
+    /// The CRN uses a construct that the format has no counterpart for.
+    #[error("{format} export does not support {construct}")]
+    Unsupported {
+        /// The format being written.
+        format: &'static str,
+        /// The construct, such as `events`.
+        construct: &'static str,
+    },
+}
+
 /// Shared behavior for stochastic and deterministic CRNs.
-#[derive(Default, Clone)]
+#[derive(Default, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Crn<T> {
     /// The CRN's reactions.
     pub rxns: Vec<Reaction>,
@@ -70,71 +216,334 @@ pub struct Crn<T> {
     pub state: State<T>,
     /// The CRN's initial state, which it reverts to on a reset.
     pub init_state: State<T>,
-    /// The name of each species.
+    /// The name of each species. Species inside a module instance are named after it, such as `c1.E`.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_fields::names"))]
     pub names: bimap::BiHashMap<usize, String>,
+    /// Named parameters that reaction rates can refer to.
+    pub params: BTreeMap<String, f64>,
+    /// Initial amounts given by expressions over parameters, such as `2 * N`, by species index. [`Crn::set_parameter`] evaluates them again, and the new amounts take effect on the next [`Crn::reset`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub init_exprs: BTreeMap<usize, Expr>,
+    /// Boundary species, written `$F = 100;`. Reactions use their amounts in rates but never change them, as for a fuel held at a fixed level. Use [`Crn::set_boundary`] to change which species they are.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub boundary: BTreeSet<usize>,
+    /// Events that change species amounts during a simulation, in the order they were defined.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub events: Vec<Event<T>>,
 }
 
 impl<T> Crn<T>
 where
     T: Clone,
 {
+    /// A CRN with the given reactions, initial amounts, species names, parameters and boundary species, starting at time zero, with no events. Boundary species are set with [`Crn::set_boundary`], so reaction deltas leave them out.
+    pub fn from_parts(
+        rxns: Vec<Reaction>,
+        amounts: Vec<T>,
+        names: bimap::BiHashMap<usize, String>,
+        params: BTreeMap<String, f64>,
+        boundary: impl IntoIterator<Item = usize>,
+    ) -> Self {
+        let state = State {
+            species: amounts,
+            time: 0.0,
+        };
+        let mut crn = Crn {
+            init_state: state.clone(),
+            rxns,
+            state,
+            names,
+            params,
+            init_exprs: BTreeMap::new(),
+            boundary: BTreeSet::new(),
+            events: Vec::new(),
+        };
+        for species in boundary {
+            crn.set_boundary(species, true);
+        }
+        crn
+    }
+
     /// Resets the CRN to its initial state.
     pub fn reset(&mut self) {
         self.state = self.init_state.clone();
     }
 }
 
+impl<T> Crn<T> {
+    /// Sets a parameter and updates the rate of every reaction, the initial amount of every species and the condition of every event that uses it. Returns the parameter's previous value.
+    ///
+    /// Nothing changes if the parameter is unknown, or if an initial amount would become invalid.
+    pub fn set_parameter(&mut self, name: &str, value: f64) -> Result<f64, ParamError>
+    where
+        T: FromStr,
+    {
+        if !self.params.contains_key(name) {
+            return Err(ParamError::UnknownParameter(name.to_string()));
+        }
+        let mut init_exprs = self.init_exprs.clone();
+        let mut amounts = Vec::new();
+        for (idx, expr) in init_exprs.iter_mut() {
+            if expr.set_param(name, value) {
+                let amount = expr.eval::<f64>(&[]);
+                let parsed = amount.to_string().parse::<T>().map_err(|_| {
+                    ParamError::InvalidAmount(self.species_names()[*idx].clone(), amount)
+                })?;
+                amounts.push((*idx, parsed));
+            }
+        }
+        self.init_exprs = init_exprs;
+        for (idx, amount) in amounts {
+            self.init_state.species[idx] = amount;
+        }
+
+        let old = std::mem::replace(self.params.get_mut(name).unwrap(), value);
+        for rxn in self.rxns.iter_mut() {
+            if rxn.rate_param.as_deref() == Some(name) {
+                rxn.rate = value;
+            }
+            if let KineticLaw::Custom(law) = &mut rxn.law {
+                law.set_param(name, value);
+            }
+        }
+        for event in self.events.iter_mut() {
+            if let Trigger::When(condition) = &mut event.trigger {
+                condition.lhs.set_param(name, value);
+                condition.rhs.set_param(name, value);
+            }
+        }
+        Ok(old)
+    }
+
+    /// Makes a species a boundary species, whose amount reactions do not change, or an ordinary species again. Updates the [`Reaction::delta`] of every reaction that uses it. Events can still change its amount.
+    pub fn set_boundary(&mut self, species: usize, boundary: bool) {
+        if boundary {
+            self.boundary.insert(species);
+        } else {
+            self.boundary.remove(&species);
+        }
+        for rxn in self.rxns.iter_mut() {
+            let reactants = rxn.reactants.get(&species);
+            let products = rxn.products.get(&species);
+            if boundary || (reactants.is_none() && products.is_none()) {
+                rxn.delta.remove(&species);
+            } else {
+                let change = products.unwrap_or(&0.0) - reactants.unwrap_or(&0.0);
+                rxn.delta.insert(species, change);
+            }
+        }
+    }
+
+    /// For each reaction, the reactions whose rates can change when it happens, in index order. These are the reactions whose rates depend on a species the reaction changes.
+    ///
+    /// The stochastic methods that use it build it once when a simulation starts, from the reactions as they are then.
+    pub fn dependents(&self) -> Vec<Vec<usize>> {
+        let mut affects: Vec<Vec<usize>> = vec![Vec::new(); self.init_state.species.len()];
+        for (idx, rxn) in self.rxns.iter().enumerate() {
+            for species in rxn.rate_species() {
+                affects[species].push(idx);
+            }
+        }
+        self.rxns
+            .iter()
+            .map(|rxn| {
+                let mut dependents: Vec<usize> = rxn
+                    .delta
+                    .iter()
+                    .filter(|(_, change)| **change != 0.0)
+                    .flat_map(|(species, _)| affects[*species].iter().copied())
+                    .collect();
+                dependents.sort_unstable();
+                dependents.dedup();
+                dependents
+            })
+            .collect()
+    }
+
+    /// Checks that the CRN uses nothing that `format` cannot express: events, or initial amounts given by expressions, which would otherwise be left out of the export without a word.
+    pub(crate) fn check_export(&self, format: &'static str) -> Result<(), ExportError> {
+        let construct = if !self.events.is_empty() {
+            "events"
+        } else if !self.init_exprs.is_empty() {
+            "initial amounts given by expressions"
+        } else {
+            return Ok(());
+        };
+        Err(ExportError::Unsupported { format, construct })
+    }
+
+    /// Returns the index of the reaction that reverses reaction `idx`, if the two were declared as a reversible pair.
+    pub fn reverse_of(&self, idx: usize) -> Option<usize> {
+        self.rxns.get(idx)?.reverse
+    }
+
+    /// Returns every reversible pair of reactions as `(forward, reverse)` indices.
+    pub fn reversible_pairs(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.rxns
+            .iter()
+            .enumerate()
+            .filter_map(|(i, rxn)| rxn.reverse.filter(|j| i < *j).map(|j| (i, j)))
+    }
+
+    /// Returns true if reactions `i` and `j` refer to each other and have mirrored stoichiometry.
+    fn is_reversible_pair(&self, i: usize, j: usize) -> bool {
+        match (self.rxns.get(i), self.rxns.get(j)) {
+            (Some(a), Some(b)) => {
+                a.reverse == Some(j)
+                    && b.reverse == Some(i)
+                    && a.reactants == b.products
+                    && a.products == b.reactants
+            }
+            _ => false,
+        }
+    }
+}
+
+impl<T> Crn<T> {
+    /// The name of each species, in index order. Species without a name get an unused one such as `s3`.
+    fn species_names(&self) -> Vec<String> {
+        let mut next = 0;
+        (0..self.init_state.species.len())
+            .map(|i| match self.names.get_by_left(&i) {
+                Some(name) => name.clone(),
+                None => loop {
+                    let name = format!("s{next}");
+                    next += 1;
+                    if !self.names.contains_right(&name) && !self.params.contains_key(&name) {
+                        break name;
+                    }
+                },
+            })
+            .collect()
+    }
+}
+
+/// Writes the CRN's parameters, initial state, reactions and events in the syntax accepted by [`Crn::parse`], so that parsing the output gives back an equal CRN. Numbers are written in their shortest exact form.
+///
+/// A reversible pair is written with `<->` when its two reactions are adjacent, as the parser creates them. Otherwise the reactions are written separately, and they no longer refer to each other once parsed.
 impl<T> Display for Crn<T>
 where
     T: Display,
 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let reactants_to_string = move |reactants: &HashMap<usize, i32>| -> String {
-            if reactants.is_empty() {
-                return String::new();
-            }
-            let mut result = reactants
+        let names: Vec<String> = self
+            .species_names()
+            .iter()
+            .map(|name| parse::format_name(name).into_owned())
+            .collect();
+
+        let reactants_to_string = |reactants: &HashMap<usize, f64>| -> String {
+            reactants
                 .iter()
-                .sorted()
-                .fold(String::new(), |acc, (i, count)| {
-                    if *count == 1 {
-                        acc + self.names.get_by_left(i).unwrap() + " + "
+                .sorted_by_key(|(i, _)| **i)
+                .map(|(i, count)| {
+                    if *count == 1.0 {
+                        names[*i].clone()
                     } else {
-                        acc + &format!("{}{} + ", count, self.names.get_by_left(i).unwrap())
+                        format!("{}{}", count, names[*i])
                     }
-                });
-            result.truncate(result.len() - 3);
-            result
+                })
+                .join(" + ")
         };
 
+        let rate_to_string = |rxn: &Reaction| match (&rxn.law, &rxn.rate_param) {
+            (KineticLaw::Custom(law), _) => law.format(&|i| names[i].clone()),
+            (KineticLaw::MassAction, Some(param)) => parse::format_name(param).into_owned(),
+            (KineticLaw::MassAction, None) => expr::format_number(rxn.rate),
+        };
+
+        let marker = |i: usize| if self.boundary.contains(&i) { "$" } else { "" };
+
         let mut result = String::new();
 
-        for (i, ct) in self.state.species.iter().enumerate() {
+        for (name, value) in &self.params {
             result.push_str(&format!(
-                "{} = {};\n",
-                self.names.get_by_left(&i).unwrap(),
-                ct
+                "param {} = {};\n",
+                parse::format_name(name),
+                expr::format_number(*value)
             ));
         }
 
-        for rxn in self.rxns.iter() {
-            result.push_str(&format!(
-                "{} -> {} : {};\n",
-                reactants_to_string(&rxn.reactants),
-                reactants_to_string(&rxn.products),
-                rxn.rate
-            ));
+        for (i, (name, ct)) in names.iter().zip(&self.init_state.species).enumerate() {
+            match self.init_exprs.get(&i) {
+                Some(expr) => result.push_str(&format!(
+                    "{}{} = {};\n",
+                    marker(i),
+                    name,
+                    expr.format(&|i| names[i].clone())
+                )),
+                None => result.push_str(&format!("{}{} = {};\n", marker(i), name, ct)),
+            }
+        }
+
+        for (i, rxn) in self.rxns.iter().enumerate() {
+            match rxn.reverse {
+                Some(j) if j == i + 1 && self.is_reversible_pair(i, j) => {
+                    result.push_str(&format!(
+                        "{} <-> {} : {}, {};\n",
+                        reactants_to_string(&rxn.reactants),
+                        reactants_to_string(&rxn.products),
+                        rate_to_string(rxn),
+                        rate_to_string(&self.rxns[j])
+                    ));
+                }
+                Some(j) if j + 1 == i && self.is_reversible_pair(i, j) => {}
+                _ => result.push_str(&format!(
+                    "{} -> {} : {};\n",
+                    reactants_to_string(&rxn.reactants),
+                    reactants_to_string(&rxn.products),
+                    rate_to_string(rxn)
+                )),
+            }
+        }
+
+        for event in &self.events {
+            let trigger = match &event.trigger {
+                Trigger::At(time) => format!("at {}", expr::format_number(*time)),
+                Trigger::When(condition) => format!(
+                    "when {} {} {}",
+                    condition.lhs.format(&|i| names[i].clone()),
+                    condition.comparison.symbol(),
+                    condition.rhs.format(&|i| names[i].clone())
+                ),
+            };
+            let changes = event
+                .changes
+                .iter()
+                .map(|change| match change {
+                    Change::Set(i, value) => format!("{} = {}", names[*i], value),
+                    Change::Add(i, value) => format!("{} += {}", names[*i], value),
+                    Change::Sub(i, value) => format!("{} -= {}", names[*i], value),
+                })
+                .join(", ");
+            result.push_str(&format!("{}: {};\n", trigger, changes));
         }
         write!(f, "{}", result)
     }
 }
 
-impl<T> From<String> for Crn<T>
+/// Parses a CRN as [`Crn::parse`] does.
+impl<T> FromStr for Crn<T>
+where
+    T: Clone + Default + FromStr,
+    <T as FromStr>::Err: std::fmt::Debug,
+{
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+/// Parses a CRN as [`Crn::parse`] does.
+impl<T> TryFrom<&str> for Crn<T>
 where
     T: Clone + Default + FromStr,
     <T as FromStr>::Err: std::fmt::Debug,
 {
-    fn from(s: String) -> Self {
-        Self::parse(&s).unwrap()
+    type Error = ParseError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        Self::parse(s)
     }
 }
@@ -0,0 +1,373 @@
+#[cfg(feature = "serde")]
+use std::collections::HashMap;
+use std::fmt::Debug;
+#[cfg(feature = "serde")]
+use std::fmt::Display;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+#[cfg(feature = "serde")]
+use crate::{
+    parse::{check_coefficient, Location},
+    KineticLaw, Trigger,
+};
+use crate::{Crn, ParseError};
+
+/// A format that a CRN can be read from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// The syntax accepted by [`Crn::parse`], in `.crn` files.
+    Native,
+    /// A CRN serialized with serde, in `.json` files. Reading it needs the `serde` feature.
+    Json,
+    /// SBML Level 3, in `.xml` and `.sbml` files.
+    Sbml,
+    /// Antimony, in `.ant` and `.antimony` files.
+    Antimony,
+    /// A BioNetGen network, in `.net` files.
+    BioNetGen,
+}
+
+impl Format {
+    /// The format of a file with the given path, judging by its extension.
+    pub fn from_extension(path: impl AsRef<Path>) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "crn" => Some(Format::Native),
+            "json" => Some(Format::Json),
+            "xml" | "sbml" => Some(Format::Sbml),
+            "ant" | "antimony" => Some(Format::Antimony),
+            "net" => Some(Format::BioNetGen),
+            _ => None,
+        }
+    }
+}
+
+/// The types a CRN's amounts can have to be read from text: `i32` for [`crate::StoCrn`] and `f64` for [`crate::DetCrn`].
+pub trait Amount: Clone + Default + FromStr<Err: Debug> {
+    /// Reads a CRN with amounts of this type from JSON, or fails if the `serde` feature is off.
+    #[doc(hidden)]
+    fn crn_from_json(text: &str) -> Result<Crn<Self>, ParseError>;
+}
+
+impl Amount for i32 {
+    fn crn_from_json(text: &str) -> Result<Crn<Self>, ParseError> {
+        json(text)
+    }
+}
+
+impl Amount for f64 {
+    fn crn_from_json(text: &str) -> Result<Crn<Self>, ParseError> {
+        json(text)
+    }
+}
+
+/// Reads a CRN from JSON with [`Crn::from_json`].
+#[cfg(feature = "serde")]
+fn json<T: Amount + serde::de::DeserializeOwned>(text: &str) -> Result<Crn<T>, ParseError> {
+    Crn::from_json(text)
+}
+
+/// Fails, as reading JSON needs the `serde` feature.
+#[cfg(not(feature = "serde"))]
+fn json<T>(_text: &str) -> Result<Crn<T>, ParseError> {
+    Err(ParseError::Json {
+        message: "reading JSON models needs the `serde` feature".to_string(),
+    })
+}
+
+impl<T: Amount> Crn<T> {
+    /// Reads a CRN from a file, in the format given by its extension. Native files are read with [`Crn::parse_file`], so their includes are found relative to them. JSON files are read as `Crn::from_json` does, and are rejected if the `serde` feature is off.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Crn<T>, ParseError> {
+        let path = path.as_ref();
+        let format = Format::from_extension(path).ok_or_else(|| ParseError::UnknownFormat {
+            path: path.to_path_buf(),
+        })?;
+        if format == Format::Native {
+            return Self::parse_file(path);
+        }
+        let text = fs::read_to_string(path).map_err(|err| ParseError::Io {
+            path: path.to_path_buf(),
+            message: err.to_string(),
+        })?;
+        Self::from_format(&text, format)
+    }
+
+    /// Reads a CRN in the given format from `reader`. Native models may not include other files, as with [`Crn::parse`].
+    pub fn from_reader(mut reader: impl Read, format: Format) -> Result<Crn<T>, ParseError> {
+        let mut text = String::new();
+        reader
+            .read_to_string(&mut text)
+            .map_err(|err| ParseError::Read {
+                message: err.to_string(),
+            })?;
+        Self::from_format(&text, format)
+    }
+
+    /// Reads a CRN from `text` in the given format.
+    fn from_format(text: &str, format: Format) -> Result<Crn<T>, ParseError> {
+        match format {
+            Format::Native => Self::parse(text),
+            Format::Json => T::crn_from_json(text),
+            Format::Sbml => Self::from_sbml(text),
+            Format::Antimony => Self::from_antimony(text),
+            Format::BioNetGen => Self::from_bionetgen(text),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: Default + FromStr + serde::de::DeserializeOwned> Crn<T> {
+    /// Reads a CRN serialized as JSON, such as with `serde_json::to_string`. The CRN is checked for consistency: species indices must be in range, coefficients must suit the amounts as in [`Crn::parse`], each reaction's `delta` must match its reactants, products and the boundary species, reversible pairs must refer to each other, and rate parameters must be declared.
+    pub fn from_json(text: &str) -> Result<Crn<T>, ParseError> {
+        let crn: Crn<T> = serde_json::from_str(text).map_err(|err| ParseError::Json {
+            message: err.to_string(),
+        })?;
+        crn.validate()
+            .map_err(|message| ParseError::Json { message })?;
+        Ok(crn)
+    }
+
+    /// Checks the CRN as described in [`Crn::from_json`]. Returns what is wrong otherwise.
+    fn validate(&self) -> Result<(), String> {
+        let n = self.init_state.species.len();
+        if self.state.species.len() != n {
+            return Err(format!(
+                "the state has {} species, but the initial state has {n}",
+                self.state.species.len()
+            ));
+        }
+        let check = |species: usize, what: &dyn Display| {
+            if species < n {
+                Ok(())
+            } else {
+                Err(format!(
+                    "{what} refers to species {species}, but there are only {n}"
+                ))
+            }
+        };
+        for species in self.names.left_values() {
+            check(*species, &"a name")?;
+        }
+        for species in &self.boundary {
+            check(*species, &"the boundary")?;
+        }
+        for (species, expr) in &self.init_exprs {
+            check(*species, &"an initial amount")?;
+            for species in expr.species() {
+                check(species, &"an initial amount")?;
+            }
+        }
+        for (i, rxn) in self.rxns.iter().enumerate() {
+            let what = format!("reaction {i}");
+            let species = rxn.reactants.keys().chain(rxn.products.keys());
+            for species in species.chain(rxn.delta.keys()) {
+                check(*species, &what)?;
+            }
+            if let KineticLaw::Custom(law) = &rxn.law {
+                for species in law.species() {
+                    check(species, &what)?;
+                }
+            }
+            for count in rxn.reactants.values().chain(rxn.products.values()) {
+                check_coefficient::<T>(*count, &count.to_string(), Location::new("", "")).map_err(
+                    |err| match err {
+                        ParseError::FractionalCoefficient { .. } => format!(
+                            "{what} has the coefficient {count}, but amounts are whole numbers"
+                        ),
+                        _ => format!("{what} has the coefficient {count}, which is not positive"),
+                    },
+                )?;
+            }
+            if let Some(j) = rxn.reverse {
+                if self.rxns.get(j).and_then(|other| other.reverse) != Some(i) {
+                    return Err(format!(
+                        "{what} is reversed by reaction {j}, which does not reverse it"
+                    ));
+                }
+            }
+            if let Some(param) = &rxn.rate_param {
+                if !self.params.contains_key(param) {
+                    return Err(format!("{what} uses the undeclared parameter {param}"));
+                }
+            }
+            let mut delta = HashMap::new();
+            for (species, count) in &rxn.products {
+                delta.insert(*species, *count);
+            }
+            for (species, count) in &rxn.reactants {
+                *delta.entry(*species).or_insert(0.0) -= count;
+            }
+            delta.retain(|species, _| !self.boundary.contains(species));
+            if delta != rxn.delta {
+                return Err(format!(
+                    "{what} changes amounts by a `delta` that does not match its reactants, products and the boundary species"
+                ));
+            }
+        }
+        for (i, event) in self.events.iter().enumerate() {
+            let what = format!("event {i}");
+            for change in &event.changes {
+                check(change.species(), &what)?;
+            }
+            if let Trigger::When(condition) = &event.trigger {
+                for species in condition
+                    .lhs
+                    .species()
+                    .into_iter()
+                    .chain(condition.rhs.species())
+                {
+                    check(species, &what)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::Format;
+    use crate::{parse::ParseError, presets, DetCrn, StoCrn};
+
+    /// Writes `text` to the file `name` in a temporary directory for the test, and returns its path.
+    fn write_file(name: &str, text: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("crn-load-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    #[test]
+    fn from_str() {
+        let crn: StoCrn = "A = 2; A -> B;".parse().unwrap();
+        assert_eq!(crn.init_state.species, vec![2, 0]);
+        assert!(StoCrn::try_from("A = 2; A -> B; A =").is_err());
+        assert!(matches!(
+            "A = 0.5;".parse::<StoCrn>(),
+            Err(ParseError::InvalidNumber { .. })
+        ));
+    }
+
+    #[test]
+    fn from_path() {
+        let crn = StoCrn::parse(presets::EQUILIBRIUM).unwrap();
+        for (name, text) in [
+            ("model.crn", crn.to_string()),
+            ("model.XML", crn.to_sbml().unwrap()),
+            ("model.ant", crn.to_antimony().unwrap()),
+        ] {
+            let read = StoCrn::from_path(write_file(name, &text)).unwrap();
+            assert_eq!(read.rxns, crn.rxns);
+            assert_eq!(read.init_state, crn.init_state);
+        }
+        let net = "begin species\n  1 A() 3\nend species\n";
+        let read = DetCrn::from_path(write_file("model.net", net)).unwrap();
+        assert_eq!(read.init_state.species, vec![3.0]);
+
+        #[cfg(feature = "serde")]
+        {
+            let json = serde_json::to_string(&crn).unwrap();
+            let read = StoCrn::from_path(write_file("model.json", &json)).unwrap();
+            assert_eq!(read.rxns, crn.rxns);
+            assert_eq!(read.init_state, crn.init_state);
+        }
+        #[cfg(not(feature = "serde"))]
+        assert!(matches!(
+            StoCrn::from_path(write_file("model.json", "{}")),
+            Err(ParseError::Json { .. })
+        ));
+
+        assert!(matches!(
+            StoCrn::from_path(write_file("model.txt", "")),
+            Err(ParseError::UnknownFormat { .. })
+        ));
+        assert!(matches!(
+            StoCrn::from_path("missing.crn"),
+            Err(ParseError::Io { .. })
+        ));
+    }
+
+    #[test]
+    fn from_reader() {
+        let crn = StoCrn::from_reader("A = 1; A -> B;".as_bytes(), Format::Native).unwrap();
+        assert_eq!(crn.rxns.len(), 1);
+        assert_eq!(
+            Format::from_extension("a/b.antimony"),
+            Some(Format::Antimony)
+        );
+        assert_eq!(Format::from_extension("model"), None);
+
+        let json = "{}".as_bytes();
+        assert!(matches!(
+            StoCrn::from_reader(json, Format::Json),
+            Err(ParseError::Json { .. })
+        ));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn from_json() {
+        let crn = StoCrn::parse("A = 1; A -> B;").unwrap();
+        let read = StoCrn::from_json(&serde_json::to_string(&crn).unwrap()).unwrap();
+        assert_eq!(read.rxns, crn.rxns);
+        assert!(matches!(
+            StoCrn::from_json("{"),
+            Err(ParseError::Json { .. })
+        ));
+
+        // species out of range, and a delta that does not match the reaction
+        let mut json: serde_json::Value = serde_json::to_value(&crn).unwrap();
+        json["rxns"][0]["reactants"] = serde_json::json!({ "7": 1.0 });
+        let err = StoCrn::from_json(&json.to_string()).unwrap_err();
+        assert!(
+            matches!(&err, ParseError::Json { message } if message.contains("species 7")),
+            "{err}"
+        );
+        let mut json: serde_json::Value = serde_json::to_value(&crn).unwrap();
+        json["rxns"][0]["delta"] = serde_json::json!({ "0": -2.0 });
+        let err = StoCrn::from_json(&json.to_string()).unwrap_err();
+        assert!(
+            matches!(&err, ParseError::Json { message } if message.contains("delta")),
+            "{err}"
+        );
+
+        // coefficients that a stochastic CRN cannot use
+        for count in [0.5, -1.0] {
+            let mut json: serde_json::Value = serde_json::to_value(&crn).unwrap();
+            json["rxns"][0]["reactants"] = serde_json::json!({ "0": count });
+            json["rxns"][0]["delta"] = serde_json::json!({ "0": -count, "1": 1.0 });
+            let err = StoCrn::from_json(&json.to_string()).unwrap_err();
+            assert!(
+                matches!(&err, ParseError::Json { message } if message.contains("coefficient")),
+                "{err}"
+            );
+        }
+        let mut json: serde_json::Value = serde_json::to_value(&crn).unwrap();
+        json["rxns"][0]["reactants"] = serde_json::json!({ "0": 0.5 });
+        json["rxns"][0]["delta"] = serde_json::json!({ "0": -0.5, "1": 1.0 });
+        assert!(DetCrn::from_json(&json.to_string()).is_ok());
+
+        // a reverse reaction that does not point back, and an undeclared parameter
+        let crn = StoCrn::parse("A = 1; A <-> B : 1, 2; param k = 1; B -> C : k;").unwrap();
+        let mut json: serde_json::Value = serde_json::to_value(&crn).unwrap();
+        json["rxns"][1]["reverse"] = serde_json::json!(2);
+        let err = StoCrn::from_json(&json.to_string()).unwrap_err();
+        assert!(
+            matches!(&err, ParseError::Json { message } if message.contains("reverse")),
+            "{err}"
+        );
+        let mut json: serde_json::Value = serde_json::to_value(&crn).unwrap();
+        json["params"] = serde_json::json!({});
+        let err = StoCrn::from_json(&json.to_string()).unwrap_err();
+        assert!(
+            matches!(&err, ParseError::Json { message } if message.contains("parameter k")),
+            "{err}"
+        );
+    }
+}
@@ -0,0 +1,203 @@
+use rand::Rng;
+
+use crate::sto::{waiting_time, Engine, Error, StoCrn};
+
+/// The time a reaction with the given rate next happens, counting from `now`. Never, if the rate is zero.
+fn firing_time<R: Rng + ?Sized>(now: f64, rate: f64, rng: &mut R) -> f64 {
+    if rate > 0.0 {
+        now + waiting_time(rate, rng)
+    } else {
+        f64::INFINITY
+    }
+}
+
+/// A priority queue of reactions ordered by firing time, earliest first, in which the time of any reaction can be changed.
+struct IndexedQueue {
+    /// The firing time of each reaction.
+    times: Vec<f64>,
+    /// A binary heap of reaction indices.
+    heap: Vec<usize>,
+    /// The position of each reaction in `heap`.
+    position: Vec<usize>,
+}
+
+impl IndexedQueue {
+    /// A queue of reactions with the given firing times.
+    fn new(times: Vec<f64>) -> Self {
+        let mut queue = Self {
+            heap: (0..times.len()).collect(),
+            position: (0..times.len()).collect(),
+            times,
+        };
+        for pos in (0..queue.heap.len() / 2).rev() {
+            queue.sift_down(pos);
+        }
+        queue
+    }
+
+    /// The reaction that happens first, and its time.
+    fn first(&self) -> Option<(usize, f64)> {
+        self.heap.first().map(|rxn| (*rxn, self.times[*rxn]))
+    }
+
+    /// Changes the firing time of a reaction.
+    fn update(&mut self, rxn: usize, time: f64) {
+        let old = std::mem::replace(&mut self.times[rxn], time);
+        if time < old {
+            self.sift_up(self.position[rxn]);
+        } else {
+            self.sift_down(self.position[rxn]);
+        }
+    }
+
+    /// Whether the reaction at heap position `a` happens before the one at `b`.
+    fn earlier(&self, a: usize, b: usize) -> bool {
+        self.times[self.heap[a]] < self.times[self.heap[b]]
+    }
+
+    /// Swaps two heap positions.
+    fn swap(&mut self, a: usize, b: usize) {
+        self.heap.swap(a, b);
+        self.position[self.heap[a]] = a;
+        self.position[self.heap[b]] = b;
+    }
+
+    /// Moves the reaction at `pos` towards the root until its parent happens no later.
+    fn sift_up(&mut self, mut pos: usize) {
+        while pos > 0 {
+            let parent = (pos - 1) / 2;
+            if !self.earlier(pos, parent) {
+                break;
+            }
+            self.swap(pos, parent);
+            pos = parent;
+        }
+    }
+
+    /// Moves the reaction at `pos` towards the leaves until its children happen no earlier.
+    fn sift_down(&mut self, mut pos: usize) {
+        loop {
+            let mut first = pos;
+            for child in [2 * pos + 1, 2 * pos + 2] {
+                if child < self.heap.len() && self.earlier(child, first) {
+                    first = child;
+                }
+            }
+            if first == pos {
+                break;
+            }
+            self.swap(pos, first);
+            pos = first;
+        }
+    }
+}
+
+/// Gibson and Bruck's next reaction method. Each reaction has a putative firing time, and the earliest one happens next. Afterwards only the reactions whose rates it changed get new times, by rescaling their remaining waiting time to the new rate.
+pub(crate) struct NextReaction {
+    /// The rate of each reaction in the current state.
+    rates: Vec<f64>,
+    /// The putative firing time of each reaction.
+    queue: IndexedQueue,
+    /// For each reaction, the reactions whose rates it can change.
+    dependents: Vec<Vec<usize>>,
+}
+
+impl NextReaction {
+    /// Prepares to simulate `crn`, drawing a firing time for each reaction.
+    pub(crate) fn new<R: Rng + ?Sized>(crn: &StoCrn, rng: &mut R) -> Self {
+        let mut engine = Self {
+            rates: Vec::new(),
+            queue: IndexedQueue::new(Vec::new()),
+            dependents: crn.dependents(),
+        };
+        engine.refresh(crn, rng);
+        engine
+    }
+}
+
+impl Engine for NextReaction {
+    fn step_until<R: Rng + ?Sized>(
+        &mut self,
+        crn: &mut StoCrn,
+        limit: f64,
+        rng: &mut R,
+    ) -> Result<bool, Error> {
+        let (next, time) = match self.queue.first() {
+            Some((next, time)) if time.is_finite() && time <= limit => (next, time),
+            _ if limit == f64::INFINITY => return Err(Error::TerminalState),
+            _ => {
+                crn.state.time = limit;
+                return Ok(false);
+            }
+        };
+        crn.state.time = time;
+        crn.state.apply(&crn.rxns[next]);
+
+        for &dependent in &self.dependents[next] {
+            if dependent == next {
+                continue;
+            }
+            let rate = crn.state.rate(&crn.rxns[dependent]);
+            let old = std::mem::replace(&mut self.rates[dependent], rate);
+            let putative = self.queue.times[dependent];
+            let new = if rate <= 0.0 {
+                f64::INFINITY
+            } else if old > 0.0 && putative.is_finite() {
+                time + (old / rate) * (putative - time)
+            } else {
+                firing_time(time, rate, rng)
+            };
+            self.queue.update(dependent, new);
+        }
+        self.rates[next] = crn.state.rate(&crn.rxns[next]);
+        self.queue
+            .update(next, firing_time(time, self.rates[next], rng));
+        Ok(true)
+    }
+
+    fn refresh<R: Rng + ?Sized>(&mut self, crn: &StoCrn, rng: &mut R) {
+        self.rates = crn.rxns.iter().map(|rxn| crn.state.rate(rxn)).collect();
+        let times = self
+            .rates
+            .iter()
+            .map(|rate| firing_time(crn.state.time, *rate, rng))
+            .collect();
+        self.queue = IndexedQueue::new(times);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::IndexedQueue;
+    use crate::{
+        sto::{seeded_rng, Method},
+        StoCrn,
+    };
+
+    #[test]
+    fn queue() {
+        let mut rng = seeded_rng(1);
+        let mut times: Vec<f64> = (0..50).map(|_| rng.gen()).collect();
+        let mut queue = IndexedQueue::new(times.clone());
+        for _ in 0..200 {
+            let rxn = rng.gen_range(0..times.len());
+            times[rxn] = rng.gen();
+            queue.update(rxn, times[rxn]);
+            let min = times.iter().copied().fold(f64::INFINITY, f64::min);
+            assert_eq!(queue.first().unwrap().1, min);
+        }
+    }
+
+    #[test]
+    fn events() {
+        let mut crn =
+            StoCrn::parse("-> A : 100; when A >= 50: A = 0, C += 1; at 2: A += 10;").unwrap();
+        let history = crn
+            .simulate_history_using(5.0, Method::NextReaction, &mut seeded_rng(4))
+            .unwrap();
+        assert!(history.iter().all(|state| state.species[0] <= 50.0));
+        assert!(crn.state.species[1] >= 8);
+    }
+}
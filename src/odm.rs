@@ -0,0 +1,166 @@
+use rand::Rng;
+
+use crate::sto::{waiting_time, Engine, Error, StoCrn};
+
+/// How many reactions happen between recomputing every rate from scratch, so that rounding errors in the running total cannot build up.
+const RECOMPUTE_INTERVAL: usize = 10_000;
+
+/// The optimized direct method. It chooses reactions as Gillespie's direct method does, but keeps every reaction's rate and their total between reactions, and after each reaction only recomputes the rates it changed. Reactions are searched in order of how often they have fired recently: a reaction that fires swaps places with the one searched just before it, so frequent reactions are found quickly.
+pub(crate) struct OptimizedDirect {
+    /// The rate of each reaction in the current state.
+    rates: Vec<f64>,
+    /// The sum of `rates`, updated as they change.
+    total: f64,
+    /// Reaction indices in the order they are searched.
+    order: Vec<usize>,
+    /// For each reaction, the reactions whose rates it can change.
+    dependents: Vec<Vec<usize>>,
+    /// Reactions since the rates were last recomputed.
+    since_recompute: usize,
+}
+
+impl OptimizedDirect {
+    /// Prepares to simulate `crn`.
+    pub(crate) fn new(crn: &StoCrn) -> Self {
+        let mut engine = Self {
+            rates: Vec::new(),
+            total: 0.0,
+            order: (0..crn.rxns.len()).collect(),
+            dependents: crn.dependents(),
+            since_recompute: 0,
+        };
+        engine.recompute(crn);
+        engine
+    }
+
+    /// Computes every rate, and their total, from scratch.
+    fn recompute(&mut self, crn: &StoCrn) {
+        self.rates = crn.rxns.iter().map(|rxn| crn.state.rate(rxn)).collect();
+        self.total = self.rates.iter().sum();
+        self.since_recompute = 0;
+    }
+
+    /// The position in `order` of the reaction chosen by `target`, a number between zero and the total rate. None if rounding in the total has left `target` past the last reaction.
+    fn search(&self, target: f64) -> Option<usize> {
+        let mut sum = 0.0;
+        self.order.iter().position(|rxn| {
+            sum += self.rates[*rxn];
+            target < sum
+        })
+    }
+}
+
+impl Engine for OptimizedDirect {
+    fn step_until<R: Rng + ?Sized>(
+        &mut self,
+        crn: &mut StoCrn,
+        limit: f64,
+        rng: &mut R,
+    ) -> Result<bool, Error> {
+        if self.total <= 0.0 || self.since_recompute >= RECOMPUTE_INTERVAL {
+            // Rounding can leave the running total at or below zero while some rates are not.
+            self.recompute(crn);
+        }
+        let mut recomputed = false;
+        let (time, pos) = loop {
+            if self.total <= 0.0 {
+                if limit == f64::INFINITY {
+                    return Err(Error::TerminalState);
+                }
+                crn.state.time = limit;
+                return Ok(false);
+            }
+
+            let time = crn.state.time + waiting_time(self.total, rng);
+            if time > limit {
+                crn.state.time = limit;
+                return Ok(false);
+            }
+            match self.search(rng.gen::<f64>() * self.total) {
+                Some(pos) => break (time, pos),
+                // The total is exact just after recomputing, so it can only be missed again if it is not finite.
+                None if recomputed => return Err(Error::InsufficientPrecision),
+                None => {
+                    // The running total has drifted above the true one; correct it and try again.
+                    self.recompute(crn);
+                    recomputed = true;
+                }
+            }
+        };
+        crn.state.time = time;
+        self.since_recompute += 1;
+        let fired = self.order[pos];
+        crn.state.apply(&crn.rxns[fired]);
+
+        for &dependent in &self.dependents[fired] {
+            let rate = crn.state.rate(&crn.rxns[dependent]);
+            self.total += rate - std::mem::replace(&mut self.rates[dependent], rate);
+        }
+        if pos > 0 {
+            self.order.swap(pos, pos - 1);
+        }
+        Ok(true)
+    }
+
+    fn refresh<R: Rng + ?Sized>(&mut self, crn: &StoCrn, _rng: &mut R) {
+        self.recompute(crn);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::OptimizedDirect;
+    use crate::{
+        sto::{seeded_rng, Engine, Method},
+        Reaction, StoCrn,
+    };
+
+    #[test]
+    fn incremental() {
+        let mut crn =
+            StoCrn::parse("A = 20; B = 20; -> C : 1; A + B -> C : 100; C -> A + B : 10;").unwrap();
+        let mut engine = OptimizedDirect::new(&crn);
+        let mut rng = seeded_rng(6);
+        for _ in 0..500 {
+            engine
+                .step_until(&mut crn, f64::INFINITY, &mut rng)
+                .unwrap();
+            let rates: Vec<f64> = crn.rxns.iter().map(|rxn| crn.state.rate(rxn)).collect();
+            assert_eq!(engine.rates, rates);
+            approx::assert_relative_eq!(engine.total, rates.iter().sum(), max_relative = 1e-9);
+        }
+
+        // the most frequent reaction moves to the front
+        let mut crn = StoCrn::parse("-> A : 1; -> B : 100;").unwrap();
+        let mut engine = OptimizedDirect::new(&crn);
+        for _ in 0..200 {
+            engine
+                .step_until(&mut crn, f64::INFINITY, &mut rng)
+                .unwrap();
+        }
+        assert_eq!(engine.order[0], 1);
+    }
+
+    #[test]
+    fn dependents() {
+        let mut crn = StoCrn::parse("A = 5; A -> B; B -> ;").unwrap();
+        assert_eq!(OptimizedDirect::new(&crn).dependents, [vec![0, 1], vec![1]]);
+        crn.set_boundary(1, true);
+        assert_eq!(OptimizedDirect::new(&crn).dependents, [vec![0], vec![]]);
+
+        // reactions added after a simulation are part of the next one
+        let mut rng = seeded_rng(7);
+        crn.steps_using(2, Method::OptimizedDirect, &mut rng)
+            .unwrap();
+        crn.rxns.push(Reaction::new(
+            HashMap::from([(1, 1.0)]),
+            HashMap::new(),
+            1.0,
+        ));
+        assert_eq!(OptimizedDirect::new(&crn).dependents.len(), 3);
+        crn.steps_using(2, Method::OptimizedDirect, &mut rng)
+            .unwrap();
+    }
+}
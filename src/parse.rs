@@ -1,91 +1,1450 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::str::FromStr;
 
 use nom::{
-    bytes::complete::tag,
-    character::complete::{alpha1, alphanumeric0, digit0, multispace0},
-    combinator::{opt, recognize},
-    multi::{many0, separated_list0},
-    number::complete::double,
-    sequence::{delimited, pair, separated_pair, terminated},
+    branch::alt,
+    bytes::complete::{is_not, tag, take_till1, take_until},
+    character::complete::{
+        alpha1, alphanumeric1, char, digit0, digit1, multispace1, not_line_ending, one_of, satisfy,
+    },
+    combinator::{consumed, cut, map, not, opt, peek, recognize},
+    error::{context, VerboseError, VerboseErrorKind},
+    multi::{many0, many0_count, many1_count, separated_list0, separated_list1},
+    number::complete::recognize_float,
+    sequence::{delimited, pair, preceded, separated_pair, terminated, tuple},
     IResult,
 };
+use thiserror::Error;
 
-use crate::{state::State, Crn, Reaction};
+use crate::{
+    event::{Change, Comparison, Condition, Event, Trigger},
+    expr::{BinOp, Expr, Func},
+    Crn, KineticLaw, Reaction,
+};
+
+/// Maximum number of characters of source text kept in a [`Location`] snippet.
+const SNIPPET_LEN: usize = 40;
+
+/// Where in the source text a parse error occurred.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Location {
+    /// Line number, starting at 1.
+    pub line: usize,
+    /// Column number in characters, starting at 1.
+    pub column: usize,
+    /// The source text at the error, up to the end of its line. Empty at the end of the input.
+    pub snippet: String,
+    /// The file containing the error, if the text was read from a file.
+    pub file: Option<PathBuf>,
+}
+
+impl Location {
+    /// Locates `fragment`, which must be a subslice of `source`.
+    pub(crate) fn new(source: &str, fragment: &str) -> Self {
+        let offset = (fragment.as_ptr() as usize)
+            .saturating_sub(source.as_ptr() as usize)
+            .min(source.len());
+        let before = &source[..offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let rest = &source[offset..];
+        let line_end = rest.find('\n').unwrap_or(rest.len());
+        Self {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+            snippet: rest[..line_end]
+                .trim_end()
+                .chars()
+                .take(SNIPPET_LEN)
+                .collect(),
+            file: None,
+        }
+    }
+
+    /// Sets the file the location is in.
+    fn in_file(mut self, file: Option<&Path>) -> Self {
+        self.file = file.map(Path::to_path_buf);
+        self
+    }
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}, ", file.display())?;
+        }
+        write!(f, "line {}, column {}", self.line, self.column)?;
+        if self.snippet.is_empty() {
+            write!(f, ", at end of input")
+        } else {
+            write!(f, ", near `{}`", self.snippet)
+        }
+    }
+}
 
 /// Errors that can occur while parsing a CRN.
-#[derive(Debug, Clone)]
+#[derive(Error, Debug, Clone, PartialEq)]
 pub enum ParseError {
-    /// Species amount was defined twice.
-    DuplicateDefinition(String),
+    /// A species amount or parameter was defined twice, or a name was used for both.
+    #[error("`{name}` is defined twice ({location})")]
+    DuplicateDefinition {
+        /// Name of the species or parameter.
+        name: String,
+        /// Location of the second definition.
+        location: Location,
+    },
+    /// The input does not match the grammar.
+    #[error("syntax error at {location}: expected {expected}")]
+    Syntax {
+        /// Description of what the parser was looking for.
+        expected: String,
+        /// Location of the offending input.
+        location: Location,
+    },
+    /// A number could not be converted to the CRN's amount or rate type.
+    #[error("invalid number `{text}` ({location})")]
+    InvalidNumber {
+        /// The number as written.
+        text: String,
+        /// Location of the number.
+        location: Location,
+    },
+    /// A stoichiometric coefficient is zero or out of range.
+    #[error("invalid stoichiometric coefficient `{text}` ({location})")]
+    InvalidCoefficient {
+        /// The coefficient as written.
+        text: String,
+        /// Location of the coefficient.
+        location: Location,
+    },
+    /// A stoichiometric coefficient is not a whole number, but the CRN's amounts are integers, as in a stochastic CRN.
+    #[error(
+        "fractional stoichiometric coefficient `{text}` needs a deterministic CRN ({location})"
+    )]
+    FractionalCoefficient {
+        /// The coefficient as written.
+        text: String,
+        /// Location of the coefficient.
+        location: Location,
+    },
+    /// A rate refers to a name that is neither a declared parameter nor a species.
+    #[error("unknown parameter `{name}` ({location})")]
+    UnknownParameter {
+        /// Name of the parameter.
+        name: String,
+        /// Location of the reference.
+        location: Location,
+    },
+    /// A rate expression calls a function that does not exist.
+    #[error("unknown function `{name}` ({location})")]
+    UnknownFunction {
+        /// Name of the function.
+        name: String,
+        /// Location of the call.
+        location: Location,
+    },
+    /// An instance refers to a module that is not defined.
+    #[error("unknown module `{name}` ({location})")]
+    UnknownModule {
+        /// Name of the module.
+        name: String,
+        /// Location of the instance.
+        location: Location,
+    },
+    /// A module contains an instance of itself, directly or through other modules.
+    #[error("module `{name}` contains itself ({location})")]
+    RecursiveModule {
+        /// Name of the module.
+        name: String,
+        /// Location of the instance that repeats it.
+        location: Location,
+    },
+    /// An included file could not be read.
+    #[error("cannot include `{}`: {message} ({location})", path.display())]
+    Include {
+        /// Path of the included file.
+        path: PathBuf,
+        /// Why it could not be read.
+        message: String,
+        /// Location of the `include` directive.
+        location: Location,
+    },
+    /// A file includes itself, directly or through other files.
+    #[error("`{}` includes itself ({location})", path.display())]
+    IncludeCycle {
+        /// Path of the file.
+        path: PathBuf,
+        /// Location of the `include` directive that repeats it.
+        location: Location,
+    },
+    /// A model file could not be read.
+    #[error("cannot read `{}`: {message}", path.display())]
+    Io {
+        /// Path of the file.
+        path: PathBuf,
+        /// Why it could not be read.
+        message: String,
+    },
+    /// A model could not be read from a reader.
+    #[error("cannot read model: {message}")]
+    Read {
+        /// Why it could not be read.
+        message: String,
+    },
+    /// A model file's extension does not name a supported format.
+    #[error("cannot tell the format of `{}` from its extension", path.display())]
+    UnknownFormat {
+        /// Path of the file.
+        path: PathBuf,
+    },
+    /// A JSON model is not a valid serialized CRN.
+    #[error("invalid JSON model: {message}")]
+    Json {
+        /// Why it is invalid.
+        message: String,
+    },
+    /// The input uses a feature that cannot be represented as a CRN.
+    #[error("{construct} are not supported ({location})")]
+    Unsupported {
+        /// Description of the unsupported construct.
+        construct: String,
+        /// Location of the construct.
+        location: Location,
+    },
+    /// Input remained after the last complete statement.
+    #[error("unexpected input at {location}")]
+    TrailingInput {
+        /// Location of the first unparsed character.
+        location: Location,
+    },
+}
+
+impl ParseError {
+    /// Where the error occurred, if it has a location.
+    pub fn location(&self) -> Option<&Location> {
+        match self {
+            ParseError::DuplicateDefinition { location, .. }
+            | ParseError::Syntax { location, .. }
+            | ParseError::InvalidNumber { location, .. }
+            | ParseError::InvalidCoefficient { location, .. }
+            | ParseError::FractionalCoefficient { location, .. }
+            | ParseError::UnknownParameter { location, .. }
+            | ParseError::UnknownFunction { location, .. }
+            | ParseError::UnknownModule { location, .. }
+            | ParseError::RecursiveModule { location, .. }
+            | ParseError::Include { location, .. }
+            | ParseError::IncludeCycle { location, .. }
+            | ParseError::Unsupported { location, .. }
+            | ParseError::TrailingInput { location } => Some(location),
+            ParseError::Io { .. }
+            | ParseError::Read { .. }
+            | ParseError::UnknownFormat { .. }
+            | ParseError::Json { .. } => None,
+        }
+    }
+}
+
+/// Result type of the parsers in this module.
+type PResult<'a, O> = IResult<&'a str, O, VerboseError<&'a str>>;
+
+/// Converts a failed parse of `source`, read from `file` if it came from one, into a [`ParseError::Syntax`].
+fn syntax_error(
+    source: &str,
+    file: Option<&Path>,
+    err: nom::Err<VerboseError<&str>>,
+) -> ParseError {
+    match err {
+        nom::Err::Error(e) | nom::Err::Failure(e) => {
+            let expected = e
+                .errors
+                .iter()
+                .find_map(|(_, kind)| match kind {
+                    VerboseErrorKind::Context(ctx) => Some(ctx.to_string()),
+                    _ => None,
+                })
+                .unwrap_or_else(|| "valid input".to_string());
+            let position = e.errors.first().map_or(&source[source.len()..], |(i, _)| i);
+            ParseError::Syntax {
+                expected,
+                location: Location::new(source, position).in_file(file),
+            }
+        }
+        nom::Err::Incomplete(_) => ParseError::Syntax {
+            expected: "more input".to_string(),
+            location: Location::new(source, &source[source.len()..]).in_file(file),
+        },
+    }
+}
+
+/// Parse whitespace and comments. Line comments start with `#` or `//`; block comments are enclosed in `/*` and `*/`.
+fn ws(input: &str) -> PResult<'_, &str> {
+    recognize(many0_count(alt((
+        multispace1,
+        recognize(pair(alt((tag("#"), tag("//"))), not_line_ending)),
+        recognize(tuple((
+            tag("/*"),
+            cut(context("`*/`", take_until("*/"))),
+            tag("*/"),
+        ))),
+    ))))(input)
+}
+
+/// Parse an identifier: a letter or underscore, then letters, digits and underscores, optionally ending in primes. For example `X_1`, `mRNA_lacI` or `A'`.
+fn identifier(input: &str) -> PResult<'_, &str> {
+    recognize(tuple((
+        alt((alpha1, tag("_"))),
+        many0_count(alt((alphanumeric1, tag("_")))),
+        many0_count(char('\'')),
+    )))(input)
+}
+
+/// Parse a quoted path such as `"leaks.crn"`, which can contain anything but double quotes and line breaks. Returns the path without its quotes.
+fn quoted_path(input: &str) -> PResult<'_, &str> {
+    preceded(
+        char('"'),
+        cut(terminated(
+            context("path", take_till1(|c| matches!(c, '"' | '\n' | '\r'))),
+            context("closing `\"`", char('"')),
+        )),
+    )(input)
+}
+
+/// Parse a quoted name such as `"ATP:Mg"`. Double quotes, backslashes and line breaks are written `\"`, `\\`, `\n` and `\r`. Returns the name as written, without its quotes; [`unescape`] gives the name itself.
+fn quoted_name(input: &str) -> PResult<'_, &str> {
+    preceded(
+        char('"'),
+        cut(terminated(
+            context(
+                "name",
+                recognize(many1_count(alt((
+                    is_not("\"\\\n\r"),
+                    recognize(pair(char('\\'), one_of("\"\\nr"))),
+                )))),
+            ),
+            context("closing `\"`", char('"')),
+        )),
+    )(input)
+}
+
+/// The name written as `name` inside quotes, with its escapes replaced by the characters they stand for. Identifiers have no escapes, and are returned unchanged.
+fn unescape(name: &str) -> Cow<'_, str> {
+    if !name.contains('\\') {
+        return Cow::Borrowed(name);
+    }
+    let mut result = String::with_capacity(name.len());
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        result.push(match c {
+            '\\' => match chars.next() {
+                Some('n') => '\n',
+                Some('r') => '\r',
+                Some(other) => other,
+                None => break,
+            },
+            c => c,
+        });
+    }
+    Cow::Owned(result)
+}
+
+/// Parse a species or parameter name, which is either an identifier or quoted.
+fn name(input: &str) -> PResult<'_, &str> {
+    alt((quoted_name, identifier))(input)
+}
+
+/// Writes a name so that it parses back to the same name, quoting it unless it is an identifier.
+pub(crate) fn format_name(name: &str) -> Cow<'_, str> {
+    match identifier(name) {
+        Ok(("", _)) => Cow::Borrowed(name),
+        _ => {
+            let escaped = name
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n")
+                .replace('\r', "\\r");
+            Cow::Owned(format!("\"{escaped}\""))
+        }
+    }
+}
+
+/// Parse a keyword that is not immediately followed by more identifier characters.
+fn keyword<'a>(kw: &'static str) -> impl FnMut(&'a str) -> PResult<'a, &'a str> {
+    terminated(
+        tag(kw),
+        not(satisfy(|c| c.is_alphanumeric() || c == '_' || c == '\'')),
+    )
 }
 
 /// Parse the name of a species.
-fn species_name(input: &str) -> IResult<&str, &str> {
+fn species_name(input: &str) -> PResult<'_, &str> {
+    delimited(ws, context("species name", name), ws)(input)
+}
+
+/// Parse a parameter declaration such as `param k = 0.5;`.
+fn parse_param(input: &str) -> PResult<'_, (&str, &str)> {
     delimited(
-        multispace0,
-        recognize(pair(alpha1, alphanumeric0)),
-        multispace0,
+        pair(ws, keyword("param")),
+        separated_pair(
+            delimited(ws, name, ws),
+            tag("="),
+            cut(terminated(
+                delimited(ws, context("number", recognize_float), ws),
+                context("`;`", tag(";")),
+            )),
+        ),
+        ws,
+    )(input)
+}
+
+/// Parse the arguments of a module or an instance, such as `(A, B; k)`: species, then optionally `;` and parameters.
+fn parse_arguments<'a, S, P>(
+    input: &'a str,
+    species: fn(&'a str) -> PResult<'a, S>,
+    param: fn(&'a str) -> PResult<'a, P>,
+) -> PResult<'a, (Vec<S>, Vec<P>)> {
+    delimited(
+        char('('),
+        pair(
+            separated_list0(char(','), delimited(ws, species, ws)),
+            map(
+                opt(preceded(
+                    char(';'),
+                    separated_list0(char(','), delimited(ws, param, ws)),
+                )),
+                Option::unwrap_or_default,
+            ),
+        ),
+        context("`)`", char(')')),
+    )(input)
+}
+
+/// Parse a module definition such as `module catalysis(S, P; k) { S + E -> P + E : k; E = 1; }`.
+fn parse_module(input: &str) -> PResult<'_, ModuleTokens<'_>> {
+    let (input, module) = preceded(pair(ws, keyword("module")), delimited(ws, name, ws))(input)?;
+    let (input, _) = peek(char('('))(input)?;
+    let (input, (species, params)) = cut(|input| parse_arguments(input, name, name))(input)?;
+    let (input, body) = preceded(
+        cut(delimited(ws, context("`{`", char('{')), ws)),
+        many0(parse_statement),
+    )(input)?;
+    let (input, _) = cut(delimited(ws, context("`}`", char('}')), ws))(input)?;
+    Ok((
+        input,
+        ModuleTokens {
+            name: module,
+            species,
+            params,
+            body,
+        },
+    ))
+}
+
+/// Parse a parameter argument of an instance: a number or a parameter name.
+fn parse_param_arg(input: &str) -> PResult<'_, RawExpr<'_>> {
+    context(
+        "number or parameter",
+        alt((
+            map(recognize_float, RawExpr::Num),
+            map(name, RawExpr::Ident),
+        )),
     )(input)
 }
 
-/// Parse a species amount definition.
-fn parse_count(input: &str) -> IResult<&str, (&str, &str)> {
+/// Parse a module instance such as `c1: catalysis(A, B; 0.5);`.
+fn parse_instance(input: &str) -> PResult<'_, InstanceTokens<'_>> {
+    let (input, instance) = terminated(delimited(ws, name, ws), char(':'))(input)?;
+    let (input, (module, (species, params))) = cut(terminated(
+        pair(
+            delimited(ws, context("module name", name), ws),
+            preceded(context("`(`", peek(char('('))), |input| {
+                parse_arguments(input, species_name, parse_param_arg)
+            }),
+        ),
+        delimited(ws, context("`;`", char(';')), ws),
+    ))(input)?;
+    Ok((
+        input,
+        InstanceTokens {
+            name: instance,
+            module,
+            species,
+            params,
+        },
+    ))
+}
+
+/// Parse an `include` directive such as `include "leaks.crn";`, returning the path. `include` is only a directive when a quoted path follows it, so it can still name a species.
+fn parse_include(input: &str) -> PResult<'_, &str> {
     delimited(
-        multispace0,
-        terminated(
+        pair(
+            ws,
+            terminated(keyword("include"), peek(pair(ws, char('"')))),
+        ),
+        cut(delimited(ws, context("quoted path", quoted_path), ws)),
+        cut(terminated(context("`;`", tag(";")), ws)),
+    )(input)
+}
+
+/// Parse a change to a species in an event, such as `A += 100`.
+fn parse_change(input: &str) -> PResult<'_, (&str, &str, &str)> {
+    tuple((
+        species_name,
+        context("`=`, `+=` or `-=`", alt((tag("="), tag("+="), tag("-=")))),
+        delimited(ws, context("amount", number), ws),
+    ))(input)
+}
+
+/// Runs `parser`, turning a failure into an ordinary error so that other alternatives are still tried.
+fn backtrack<'a, O>(
+    mut parser: impl FnMut(&'a str) -> PResult<'a, O>,
+) -> impl FnMut(&'a str) -> PResult<'a, O> {
+    move |input| {
+        parser(input).map_err(|err| match err {
+            nom::Err::Failure(e) => nom::Err::Error(e),
+            err => err,
+        })
+    }
+}
+
+/// Parse the trigger of an event, such as `at 5` or `when A > 50`, up to its `:`.
+fn parse_trigger(input: &str) -> PResult<'_, TriggerTokens<'_>> {
+    alt((
+        map(
+            delimited(
+                pair(ws, keyword("at")),
+                delimited(ws, number, ws),
+                char(':'),
+            ),
+            TriggerTokens::At,
+        ),
+        map(
+            preceded(
+                pair(ws, keyword("when")),
+                tuple((
+                    backtrack(parse_expr),
+                    alt((tag("<="), tag(">="), tag("<"), tag(">"))),
+                    cut(terminated(parse_expr, context("`:`", char(':')))),
+                )),
+            ),
+            |(lhs, comparison, rhs)| TriggerTokens::When(lhs, comparison, rhs),
+        ),
+    ))(input)
+}
+
+/// Parse an event such as `at 5: A += 100, B = 0;` or `when A > 50: B = 0;`.
+fn parse_event(input: &str) -> PResult<'_, EventTokens<'_>> {
+    let (input, trigger) = parse_trigger(input)?;
+    let (input, changes) = cut(terminated(
+        separated_list1(char(','), parse_change),
+        pair(context("`;`", char(';')), ws),
+    ))(input)?;
+    Ok((input, EventTokens { trigger, changes }))
+}
+
+/// Parse a species amount definition, such as `A = 10;` or `A = 2 * N;`. A `$` before the name, as in `$F = 100;`, makes it a boundary species.
+fn parse_count(input: &str) -> PResult<'_, Statement<'_>> {
+    map(
+        delimited(
+            ws,
             separated_pair(
-                species_name,
-                separated_pair(multispace0, tag("="), multispace0),
-                nom::number::complete::recognize_float,
+                pair(opt(char('$')), species_name),
+                tag("="),
+                cut(terminated(consumed(parse_expr), context("`;`", tag(";")))),
             ),
-            tag(";"),
+            ws,
         ),
-        multispace0,
+        |((dollar, species), (text, amount))| {
+            Statement::Count(species, text, amount, dollar.is_some())
+        },
     )(input)
 }
 
-/// Parse multiple species amount definitions.
-fn parse_counts(input: &str) -> IResult<&str, Vec<(&str, &str)>> {
-    many0(parse_count)(input)
+/// Parse a stoichiometric coefficient, such as `2` or `0.5`. There is no exponent, so that `2e` can be a coefficient and a species.
+fn coefficient(input: &str) -> PResult<'_, &str> {
+    recognize(pair(digit1, opt(pair(char('.'), digit1))))(input)
 }
 
 /// Parse a species with an optional stoichiometric coefficient.
-fn parse_reactant(input: &str) -> IResult<&str, (&str, &str)> {
-    delimited(multispace0, pair(digit0, species_name), multispace0)(input)
+fn parse_reactant(input: &str) -> PResult<'_, (&str, &str)> {
+    delimited(
+        ws,
+        pair(
+            map(opt(coefficient), Option::unwrap_or_default),
+            species_name,
+        ),
+        ws,
+    )(input)
+}
+
+/// Checks that `value`, written as `text` at `location`, can be a stoichiometric coefficient in a CRN whose amounts have type `T`. Integer amounts, as in a stochastic CRN, need whole-number coefficients.
+pub(crate) fn check_coefficient<T: FromStr>(
+    value: f64,
+    text: &str,
+    location: Location,
+) -> Result<f64, ParseError> {
+    let text = text.to_string();
+    if !(value.is_finite() && value > 0.0) {
+        Err(ParseError::InvalidCoefficient { text, location })
+    } else if value.to_string().parse::<T>().is_ok() {
+        Ok(value)
+    } else if value.fract() != 0.0 {
+        Err(ParseError::FractionalCoefficient { text, location })
+    } else {
+        Err(ParseError::InvalidCoefficient { text, location })
+    }
 }
 
 /// Parse multiple species with optional stoichiometric coefficients.
-fn parse_reactants(input: &str) -> IResult<&str, Vec<(&str, &str)>> {
+fn parse_reactants(input: &str) -> PResult<'_, Vec<(&str, &str)>> {
     delimited(
-        multispace0,
-        separated_list0(
-            delimited(multispace0, tag("+"), multispace0),
-            parse_reactant,
-        ),
-        multispace0,
+        ws,
+        separated_list0(delimited(ws, tag("+"), ws), parse_reactant),
+        ws,
     )(input)
 }
 
 /// Result of parsing a reaction.
-type ReactionTokens<'a> = (
-    (Vec<(&'a str, &'a str)>, Vec<(&'a str, &'a str)>),
-    Option<f64>,
-);
+struct ReactionTokens<'a> {
+    /// Reactants and their coefficients.
+    reactants: Vec<(&'a str, &'a str)>,
+    /// Products and their coefficients.
+    products: Vec<(&'a str, &'a str)>,
+    /// Whether the reaction was written with `<->`.
+    reversible: bool,
+    /// The rates, if given. Reversible reactions have a forward and a reverse rate.
+    rates: Vec<RawExpr<'a>>,
+}
 
-/// Parse a reaction with an optional rate parameter.
-fn parse_reaction(input: &str) -> IResult<&str, ReactionTokens> {
-    terminated(
-        pair(
-            separated_pair(parse_reactants, tag("->"), parse_reactants),
-            opt(delimited(pair(tag(":"), multispace0), double, multispace0)),
+/// An expression as written, before names are resolved to species and parameters.
+#[derive(Clone, Debug)]
+enum RawExpr<'a> {
+    /// A number.
+    Num(&'a str),
+    /// The name of a species or parameter.
+    Ident(&'a str),
+    /// Negation.
+    Neg(Box<RawExpr<'a>>),
+    /// A binary operation.
+    BinOp(BinOp, Box<RawExpr<'a>>, Box<RawExpr<'a>>),
+    /// A function call.
+    Call(&'a str, Vec<RawExpr<'a>>),
+}
+
+/// Parse an unsigned number. Unlike [`recognize_float`], a trailing `e` that does not start an exponent is left alone.
+fn number(input: &str) -> PResult<'_, &str> {
+    recognize(pair(
+        alt((
+            recognize(pair(digit1, opt(pair(char('.'), digit0)))),
+            recognize(pair(char('.'), digit1)),
+        )),
+        opt(tuple((one_of("eE"), opt(one_of("+-")), digit1))),
+    ))(input)
+}
+
+/// Parse a number, a name, a function call, or a parenthesized expression.
+fn parse_atom(input: &str) -> PResult<'_, RawExpr<'_>> {
+    delimited(
+        ws,
+        context(
+            "expression",
+            alt((
+                map(number, RawExpr::Num),
+                map(
+                    pair(
+                        identifier,
+                        preceded(
+                            pair(ws, char('(')),
+                            cut(terminated(
+                                separated_list0(char(','), parse_expr),
+                                context("`)`", char(')')),
+                            )),
+                        ),
+                    ),
+                    |(name, args)| RawExpr::Call(name, args),
+                ),
+                map(name, RawExpr::Ident),
+                preceded(
+                    char('('),
+                    cut(terminated(parse_expr, context("`)`", char(')')))),
+                ),
+            )),
         ),
-        tag(";"),
+        ws,
     )(input)
 }
 
-/// Parse multiple reactions.
-fn parse_reactions(input: &str) -> IResult<&str, Vec<ReactionTokens>> {
-    many0(parse_reaction)(input)
+/// Parse an exponentiation, which is right-associative and binds tighter than negation.
+fn parse_power(input: &str) -> PResult<'_, RawExpr<'_>> {
+    let (input, base) = parse_atom(input)?;
+    let (input, exponent) = opt(preceded(char('^'), cut(parse_unary)))(input)?;
+    Ok(match exponent {
+        Some(exponent) => (
+            input,
+            RawExpr::BinOp(BinOp::Pow, Box::new(base), Box::new(exponent)),
+        ),
+        None => (input, base),
+    })
+}
+
+/// Parse an optionally negated expression.
+fn parse_unary(input: &str) -> PResult<'_, RawExpr<'_>> {
+    alt((
+        map(preceded(pair(ws, char('-')), cut(parse_unary)), |e| {
+            RawExpr::Neg(Box::new(e))
+        }),
+        parse_power,
+    ))(input)
+}
+
+/// Parse a left-associative chain of `operand`s joined by the operators in `ops`.
+fn left_assoc<'a>(
+    input: &'a str,
+    operand: fn(&'a str) -> PResult<'a, RawExpr<'a>>,
+    ops: &[(char, BinOp)],
+) -> PResult<'a, RawExpr<'a>> {
+    let (mut input, mut result) = operand(input)?;
+    while let Some((_, op)) = ops.iter().find(|(c, _)| input.starts_with(*c)) {
+        let (rest, rhs) = cut(operand)(&input[1..])?;
+        result = RawExpr::BinOp(*op, Box::new(result), Box::new(rhs));
+        input = rest;
+    }
+    Ok((input, result))
+}
+
+/// Parse a product or quotient.
+fn parse_term(input: &str) -> PResult<'_, RawExpr<'_>> {
+    left_assoc(input, parse_unary, &[('*', BinOp::Mul), ('/', BinOp::Div)])
+}
+
+/// Parse an arithmetic expression over numbers, species, parameters and built-in functions.
+fn parse_expr(input: &str) -> PResult<'_, RawExpr<'_>> {
+    left_assoc(input, parse_term, &[('+', BinOp::Add), ('-', BinOp::Sub)])
+}
+
+/// Parse a reaction with an optional rate. A reversible reaction takes a forward and a reverse rate.
+fn parse_reaction(input: &str) -> PResult<'_, ReactionTokens<'_>> {
+    let (input, reactants) = preceded(ws, parse_reactants)(input)?;
+    let (input, arrow) = context("`->` or `<->`", alt((tag("<->"), tag("->"))))(input)?;
+    let reversible = arrow == "<->";
+    let (input, products) = cut(parse_reactants)(input)?;
+    let (input, rates) = if reversible {
+        cut(opt(preceded(
+            tag(":"),
+            cut(separated_pair(
+                parse_expr,
+                context("`,` and a reverse rate", tag(",")),
+                parse_expr,
+            )),
+        )))(input)
+        .map(|(input, rates)| (input, rates.map_or(vec![], |(f, r)| vec![f, r])))?
+    } else {
+        cut(opt(preceded(tag(":"), cut(parse_expr))))(input)
+            .map(|(input, rate)| (input, rate.into_iter().collect()))?
+    };
+    let (input, _) = terminated(cut(context("`;`", tag(";"))), ws)(input)?;
+    Ok((
+        input,
+        ReactionTokens {
+            reactants,
+            products,
+            reversible,
+            rates,
+        },
+    ))
+}
+
+/// Parse a number as `T`, rejecting values too large to be finite, such as `1e400`, which could not be written back.
+fn parse_finite<T: FromStr>(num: &str) -> Option<T> {
+    num.parse::<f64>().ok().filter(|x| x.is_finite())?;
+    num.parse().ok()
+}
+
+/// Resolve the names in an expression with `lookup`, locating errors with `locate`. Names it does not know are reported as unknown parameters.
+fn resolve<'a>(
+    locate: &dyn Fn(&str) -> Location,
+    raw: &RawExpr<'a>,
+    lookup: &dyn Fn(&str) -> Option<Expr>,
+) -> Result<Expr, ParseError> {
+    Ok(match raw {
+        RawExpr::Num(num) => {
+            Expr::Num(parse_finite(num).ok_or_else(|| ParseError::InvalidNumber {
+                text: num.to_string(),
+                location: locate(num),
+            })?)
+        }
+        RawExpr::Ident(name) => lookup(name).ok_or_else(|| ParseError::UnknownParameter {
+            name: name.to_string(),
+            location: locate(name),
+        })?,
+        RawExpr::Neg(e) => match resolve(locate, e, lookup)? {
+            Expr::Num(x) => Expr::Num(-x),
+            e => Expr::Neg(Box::new(e)),
+        },
+        RawExpr::BinOp(op, a, b) => Expr::BinOp(
+            *op,
+            Box::new(resolve(locate, a, lookup)?),
+            Box::new(resolve(locate, b, lookup)?),
+        ),
+        RawExpr::Call(name, args) => {
+            let f = Func::from_name(name).ok_or_else(|| ParseError::UnknownFunction {
+                name: name.to_string(),
+                location: locate(name),
+            })?;
+            if args.len() != f.arity() {
+                return Err(ParseError::Syntax {
+                    expected: format!("{} arguments to `{}`", f.arity(), f.name()),
+                    location: locate(name),
+                });
+            }
+            Expr::Call(
+                f,
+                args.iter()
+                    .map(|arg| resolve(locate, arg, lookup))
+                    .collect::<Result<_, _>>()?,
+            )
+        }
+    })
+}
+
+/// Parse `text`, which must be a subslice of `source`, as a single expression and resolve its names with `lookup`.
+pub(crate) fn parse_expression(
+    source: &str,
+    text: &str,
+    lookup: &dyn Fn(&str) -> Option<Expr>,
+) -> Result<Expr, ParseError> {
+    let (rest, raw) = parse_expr(text).map_err(|e| syntax_error(source, None, e))?;
+    if !rest.is_empty() {
+        return Err(ParseError::TrailingInput {
+            location: Location::new(source, rest),
+        });
+    }
+    resolve(&|fragment| Location::new(source, fragment), &raw, lookup)
+}
+
+/// A single statement of a CRN definition.
+enum Statement<'a> {
+    /// A parameter declaration.
+    Param(&'a str, &'a str),
+    /// A species amount definition, and whether the species is a boundary species.
+    Count(&'a str, &'a str, RawExpr<'a>, bool),
+    /// A reaction.
+    Reaction(ReactionTokens<'a>),
+    /// A module definition.
+    Module(ModuleTokens<'a>),
+    /// An instance of a module.
+    Instance(InstanceTokens<'a>),
+    /// An `include` directive, with the path as written.
+    Include(&'a str),
+    /// An event.
+    Event(EventTokens<'a>),
+}
+
+/// The trigger of an event as written.
+#[derive(Clone)]
+enum TriggerTokens<'a> {
+    /// The time of a timed event.
+    At(&'a str),
+    /// The sides of a condition, and the comparison between them.
+    When(RawExpr<'a>, &'a str, RawExpr<'a>),
+}
+
+/// Result of parsing an event such as `at 5: A += 100, B = 0;`.
+struct EventTokens<'a> {
+    /// When the event happens.
+    trigger: TriggerTokens<'a>,
+    /// Each change: a species, the operator `=`, `+=` or `-=`, and the amount.
+    changes: Vec<(&'a str, &'a str, &'a str)>,
+}
+
+/// Result of parsing a module definition.
+struct ModuleTokens<'a> {
+    /// Name of the module.
+    name: &'a str,
+    /// Names of the species the module takes as arguments.
+    species: Vec<&'a str>,
+    /// Names of the parameters the module takes as arguments.
+    params: Vec<&'a str>,
+    /// The statements of the module.
+    body: Vec<Statement<'a>>,
+}
+
+/// Result of parsing a module instance.
+struct InstanceTokens<'a> {
+    /// Name of the instance, which prefixes the names of its internal species and parameters.
+    name: &'a str,
+    /// Name of the module.
+    module: &'a str,
+    /// Species passed to the module.
+    species: Vec<&'a str>,
+    /// Numbers or parameter names passed to the module.
+    params: Vec<RawExpr<'a>>,
+}
+
+/// Parse any statement.
+fn parse_statement(input: &str) -> PResult<'_, Statement<'_>> {
+    alt((
+        map(parse_param, |(name, num)| Statement::Param(name, num)),
+        map(parse_module, Statement::Module),
+        map(parse_instance, Statement::Instance),
+        map(parse_include, Statement::Include),
+        map(parse_event, Statement::Event),
+        parse_count,
+        map(parse_reaction, Statement::Reaction),
+    ))(input)
+}
+
+/// Parse a whole CRN definition, read from `file` if it came from one, into statements.
+fn parse_statements<'a>(
+    source: &'a str,
+    file: Option<&Path>,
+) -> Result<Vec<Statement<'a>>, ParseError> {
+    let mut input = source;
+    let mut result = Vec::new();
+    loop {
+        match parse_statement(input) {
+            Ok((rest, statement)) => {
+                result.push(statement);
+                input = rest;
+            }
+            Err(nom::Err::Error(_)) => break,
+            Err(e) => return Err(syntax_error(source, file, e)),
+        }
+    }
+    let (rest, _) = ws(input).map_err(|e| syntax_error(source, file, e))?;
+    if rest.is_empty() {
+        Ok(result)
+    } else {
+        Err(ParseError::TrailingInput {
+            location: Location::new(source, rest).in_file(file),
+        })
+    }
+}
+
+/// The text of a CRN definition, and where it came from.
+struct Source<'a> {
+    /// The file the text was read from, if any.
+    path: Option<PathBuf>,
+    /// The text.
+    text: &'a str,
+    /// The sources named by the text's `include` directives, in order, as indices into the list of sources.
+    includes: Vec<usize>,
+}
+
+/// Locate `fragment`, which must be a subslice of one of `sources`.
+fn locate(sources: &[Source], fragment: &str) -> Location {
+    let ptr = fragment.as_ptr() as usize;
+    let source = sources
+        .iter()
+        .find(|source| {
+            let start = source.text.as_ptr() as usize;
+            (start..=start + source.text.len()).contains(&ptr)
+        })
+        .unwrap_or(&sources[0]);
+    Location::new(source.text, fragment).in_file(source.path.as_deref())
+}
+
+/// Note the files named by the `include` directives among `statements`, the statements of `sources[idx]`. A path is resolved relative to the directory of the file that includes it. Text that did not come from a file may not include others, so that parsing a string never reads files. A file included more than once is read once: `seen` holds the index of every file already read or waiting in `pending`, by canonical path, and a new one is added to `pending` with where it was included.
+fn queue_includes(
+    sources: &mut [Source],
+    idx: usize,
+    statements: &[Statement],
+    seen: &mut HashMap<PathBuf, usize>,
+    pending: &mut VecDeque<(PathBuf, Location)>,
+) -> Result<(), ParseError> {
+    let mut directives = statements.iter().filter_map(|statement| match statement {
+        Statement::Include(path) => Some(*path),
+        _ => None,
+    });
+    let Some(file) = sources[idx].path.clone() else {
+        return match directives.next() {
+            Some(include) => Err(ParseError::Unsupported {
+                construct: "`include` outside a model file".to_string(),
+                location: locate(sources, include),
+            }),
+            None => Ok(()),
+        };
+    };
+    let dir = file.parent().unwrap_or(Path::new(""));
+    for include in directives {
+        let path = dir.join(include);
+        let location = locate(sources, include);
+        let canonical = fs::canonicalize(&path).map_err(|err| ParseError::Include {
+            path: path.clone(),
+            message: err.to_string(),
+            location: location.clone(),
+        })?;
+        let next = sources.len() + pending.len();
+        let included = *seen.entry(canonical).or_insert_with(|| {
+            pending.push_back((path, location));
+            next
+        });
+        sources[idx].includes.push(included);
+    }
+    Ok(())
+}
+
+/// List the statements of source `idx`, with each `include` directive replaced by the statements of the source it names, unless that source is already listed. `stack` holds the sources being listed, which may not include themselves.
+fn expand_includes<'s, 'a>(
+    statements: &'s [Vec<Statement<'a>>],
+    sources: &[Source],
+    idx: usize,
+    stack: &mut Vec<usize>,
+    listed: &mut HashSet<usize>,
+    out: &mut Vec<&'s Statement<'a>>,
+) -> Result<(), ParseError> {
+    listed.insert(idx);
+    stack.push(idx);
+    let mut includes = sources[idx].includes.iter();
+    for statement in &statements[idx] {
+        match statement {
+            Statement::Include(include) => {
+                let included = *includes.next().expect("every include was loaded");
+                if stack.contains(&included) {
+                    let dir = sources[idx].path.as_deref().and_then(Path::parent);
+                    return Err(ParseError::IncludeCycle {
+                        path: dir.unwrap_or(Path::new("")).join(include),
+                        location: locate(sources, include),
+                    });
+                }
+                if !listed.contains(&included) {
+                    expand_includes(statements, sources, included, stack, listed, out)?;
+                }
+            }
+            statement => out.push(statement),
+        }
+    }
+    stack.pop();
+    Ok(())
+}
+
+/// Maps the names used inside a module instance to names in the flattened CRN.
+#[derive(Default)]
+struct Scope<'a> {
+    /// Prepended to the names of the instance's own species and parameters, such as `c1.`. Empty outside modules.
+    prefix: String,
+    /// The species and parameters passed to the instance, by their names inside the module.
+    args: HashMap<&'a str, String>,
+}
+
+impl Scope<'_> {
+    /// The name in the flattened CRN of `name` as used in this scope.
+    fn global(&self, name: &str) -> String {
+        match self.args.get(name) {
+            Some(arg) => arg.clone(),
+            None => format!("{}{}", self.prefix, unescape(name)),
+        }
+    }
+}
+
+/// Assembles a CRN from parsed statements.
+struct Builder<'a, T> {
+    /// The text being parsed, used to locate errors.
+    sources: &'a [Source<'a>],
+    /// Index of each species seen so far, by its name in the flattened CRN.
+    species_map: HashMap<String, usize>,
+    /// Species whose amount has been defined explicitly.
+    defined: HashSet<usize>,
+    /// Names of the species, by index.
+    names: bimap::BiHashMap<usize, String>,
+    /// Initial amount of each species.
+    amounts: Vec<T>,
+    /// Initial amounts given by expressions over parameters, by species index.
+    init_exprs: BTreeMap<usize, Expr>,
+    /// Reactions seen so far.
+    rxns: Vec<Reaction>,
+    /// Declared parameters.
+    params: BTreeMap<String, f64>,
+    /// Names of the declared parameters.
+    param_names: HashSet<String>,
+    /// Parameters passed to module instances by name, which must be declared, with where they were passed.
+    param_args: Vec<(String, &'a str)>,
+    /// Names of the module instances, with their enclosing instances.
+    instances: HashSet<String>,
+    /// Rate laws that are resolved once every species is known, by reaction index.
+    laws: Vec<(usize, Rc<Scope<'a>>, RawExpr<'a>)>,
+    /// Boundary species.
+    boundary: Vec<usize>,
+    /// Events, in order.
+    events: Vec<Event<T>>,
+    /// The sides of event conditions, which are resolved once every species is known, by event index.
+    conditions: Vec<(usize, Rc<Scope<'a>>, RawExpr<'a>, RawExpr<'a>)>,
+}
+
+impl<'a, T> Builder<'a, T>
+where
+    T: Default + Clone + FromStr,
+{
+    /// Create an empty builder for `sources`.
+    fn new(sources: &'a [Source<'a>]) -> Self {
+        Self {
+            sources,
+            species_map: HashMap::new(),
+            defined: HashSet::new(),
+            names: bimap::BiHashMap::new(),
+            amounts: Vec::new(),
+            init_exprs: BTreeMap::new(),
+            rxns: Vec::new(),
+            params: BTreeMap::new(),
+            param_names: HashSet::new(),
+            param_args: Vec::new(),
+            instances: HashSet::new(),
+            laws: Vec::new(),
+            boundary: Vec::new(),
+            events: Vec::new(),
+            conditions: Vec::new(),
+        }
+    }
+
+    /// Locate `fragment` within the source text.
+    fn location(&self, fragment: &str) -> Location {
+        locate(self.sources, fragment)
+    }
+
+    /// Replace each module instance in `statements` with the statements of its module, recursively, and pair every statement with the scope its names belong to.
+    fn flatten<'s>(
+        &mut self,
+        modules: &HashMap<&'a str, &'s ModuleTokens<'a>>,
+        scope: Rc<Scope<'a>>,
+        statements: &[&'s Statement<'a>],
+        stack: &mut Vec<&'a str>,
+        out: &mut Vec<(Rc<Scope<'a>>, &'s Statement<'a>)>,
+    ) -> Result<(), ParseError> {
+        for &statement in statements {
+            let instance = match statement {
+                Statement::Module(module) if !stack.is_empty() => {
+                    return Err(ParseError::Unsupported {
+                        construct: "module definitions inside modules".to_string(),
+                        location: self.location(module.name),
+                    })
+                }
+                Statement::Module(_) => continue,
+                Statement::Include(path) => {
+                    return Err(ParseError::Unsupported {
+                        construct: "includes inside modules".to_string(),
+                        location: self.location(path),
+                    })
+                }
+                Statement::Instance(instance) => instance,
+                _ => {
+                    out.push((scope.clone(), statement));
+                    continue;
+                }
+            };
+            let module = modules
+                .get(instance.module)
+                .ok_or_else(|| ParseError::UnknownModule {
+                    name: instance.module.to_string(),
+                    location: self.location(instance.module),
+                })?;
+            if stack.contains(&module.name) {
+                return Err(ParseError::RecursiveModule {
+                    name: module.name.to_string(),
+                    location: self.location(instance.module),
+                });
+            }
+            if instance.species.len() != module.species.len()
+                || instance.params.len() != module.params.len()
+            {
+                return Err(ParseError::Syntax {
+                    expected: format!(
+                        "{} species and {} parameters as arguments to `{}`",
+                        module.species.len(),
+                        module.params.len(),
+                        module.name
+                    ),
+                    location: self.location(instance.module),
+                });
+            }
+            let prefix = format!("{}.", scope.global(instance.name));
+            if !self.instances.insert(prefix.clone()) {
+                return Err(ParseError::DuplicateDefinition {
+                    name: instance.name.to_string(),
+                    location: self.location(instance.name),
+                });
+            }
+            let mut inner = Scope {
+                prefix,
+                args: HashMap::new(),
+            };
+            for (name, arg) in module.species.iter().zip(&instance.species) {
+                if inner.args.insert(name, scope.global(arg)).is_some() {
+                    return Err(ParseError::DuplicateDefinition {
+                        name: name.to_string(),
+                        location: self.location(name),
+                    });
+                }
+            }
+            for (name, arg) in module.params.iter().zip(&instance.params) {
+                let global = match arg {
+                    RawExpr::Ident(arg) => {
+                        let global = scope.global(arg);
+                        self.param_args.push((global.clone(), arg));
+                        global
+                    }
+                    RawExpr::Num(num) => {
+                        let global = inner.global(name);
+                        self.add_param(global.clone(), name, num)?;
+                        global
+                    }
+                    _ => unreachable!("parameter arguments are numbers or names"),
+                };
+                if inner.args.insert(name, global).is_some() {
+                    return Err(ParseError::DuplicateDefinition {
+                        name: name.to_string(),
+                        location: self.location(name),
+                    });
+                }
+            }
+            stack.push(module.name);
+            let body: Vec<_> = module.body.iter().collect();
+            self.flatten(modules, Rc::new(inner), &body, stack, out)?;
+            stack.pop();
+        }
+        Ok(())
+    }
+
+    /// Index of the species called `name` in `scope`, adding it with a default amount if it is new.
+    fn species(&mut self, scope: &Scope<'a>, name: &'a str) -> Result<usize, ParseError> {
+        let global = scope.global(name);
+        if self.param_names.contains(&global) {
+            return Err(ParseError::DuplicateDefinition {
+                name: global,
+                location: self.location(name),
+            });
+        }
+        let len = self.names.len();
+        Ok(
+            *self.species_map.entry(global).or_insert_with_key(|global| {
+                self.names.insert(len, global.clone());
+                self.amounts.push(T::default());
+                len
+            }),
+        )
+    }
+
+    /// Add a statement in `scope` to the CRN. Parameters are handled separately by [`Builder::add_param`].
+    fn add(&mut self, scope: &Rc<Scope<'a>>, statement: &Statement<'a>) -> Result<(), ParseError> {
+        match statement {
+            Statement::Count(species, text, amount, boundary) => {
+                self.add_count(scope, species, text, amount)?;
+                if *boundary {
+                    let idx = self.species(scope, species)?;
+                    self.boundary.push(idx);
+                }
+                Ok(())
+            }
+            Statement::Reaction(reaction) => self.add_reaction(scope, reaction),
+            Statement::Event(event) => self.add_event(scope, event),
+            Statement::Param(..)
+            | Statement::Module(_)
+            | Statement::Instance(_)
+            | Statement::Include(_) => Ok(()),
+        }
+    }
+
+    /// Declare the parameter `global`, written as `name` with the value `num`.
+    fn add_param(&mut self, global: String, name: &'a str, num: &'a str) -> Result<(), ParseError> {
+        if !self.param_names.insert(global.clone()) {
+            return Err(ParseError::DuplicateDefinition {
+                name: global,
+                location: self.location(name),
+            });
+        }
+        let value = self.number(num)?;
+        self.params.insert(global, value);
+        Ok(())
+    }
+
+    /// Define the initial amount of a species.
+    fn add_count(
+        &mut self,
+        scope: &Scope<'a>,
+        species: &'a str,
+        text: &'a str,
+        amount: &RawExpr<'a>,
+    ) -> Result<(), ParseError> {
+        let idx = self.species(scope, species)?;
+        if !self.defined.insert(idx) {
+            return Err(ParseError::DuplicateDefinition {
+                name: scope.global(species),
+                location: self.location(species),
+            });
+        }
+        let num = match amount {
+            RawExpr::Num(num) => num.to_string(),
+            amount => {
+                let expr = resolve(&|fragment| self.location(fragment), amount, &|name| {
+                    let name = scope.global(name);
+                    self.params
+                        .get(&name)
+                        .map(|value| Expr::Param(name.clone(), *value))
+                })?;
+                let value = expr.eval::<f64>(&[]);
+                if !matches!(expr, Expr::Num(_)) {
+                    self.init_exprs.insert(idx, expr);
+                }
+                value.to_string()
+            }
+        };
+        self.amounts[idx] = parse_finite(&num).ok_or_else(|| ParseError::InvalidNumber {
+            text: num,
+            location: self.location(text.trim_start()),
+        })?;
+        Ok(())
+    }
+
+    /// Add an event to the CRN. A condition is resolved when the CRN is built.
+    fn add_event(
+        &mut self,
+        scope: &Rc<Scope<'a>>,
+        tokens: &EventTokens<'a>,
+    ) -> Result<(), ParseError> {
+        let trigger = match &tokens.trigger {
+            TriggerTokens::At(time) => Trigger::At(self.number(time)?),
+            TriggerTokens::When(lhs, comparison, rhs) => {
+                self.conditions
+                    .push((self.events.len(), scope.clone(), lhs.clone(), rhs.clone()));
+                Trigger::When(Condition {
+                    lhs: Expr::Num(0.0),
+                    comparison: Comparison::from_symbol(comparison)
+                        .expect("the parser only accepts comparisons"),
+                    rhs: Expr::Num(0.0),
+                })
+            }
+        };
+        let mut changes = Vec::new();
+        for (species, op, amount) in &tokens.changes {
+            let idx = self.species(scope, species)?;
+            let amount = parse_finite(amount).ok_or_else(|| ParseError::InvalidNumber {
+                text: amount.to_string(),
+                location: self.location(amount),
+            })?;
+            changes.push(match *op {
+                "=" => Change::Set(idx, amount),
+                "+=" => Change::Add(idx, amount),
+                _ => Change::Sub(idx, amount),
+            });
+        }
+        self.events.push(Event { trigger, changes });
+        Ok(())
+    }
+
+    /// Convert a list of species and coefficients into a stoichiometry map.
+    fn stoichiometry(
+        &mut self,
+        scope: &Scope<'a>,
+        side: &[(&'a str, &'a str)],
+    ) -> Result<HashMap<usize, f64>, ParseError> {
+        let mut map = HashMap::new();
+        for (num, species) in side {
+            let num = if num.is_empty() {
+                1.0
+            } else {
+                let value = self.number(num)?;
+                check_coefficient::<T>(value, num, self.location(num))?
+            };
+            let idx = self.species(scope, species)?;
+            *map.entry(idx).or_insert(0.0) += num;
+        }
+        Ok(map)
+    }
+
+    /// Parse a number.
+    fn number(&self, num: &'a str) -> Result<f64, ParseError> {
+        parse_finite(num).ok_or_else(|| ParseError::InvalidNumber {
+            text: num.to_string(),
+            location: self.location(num),
+        })
+    }
+
+    /// Resolve names in an expression in `scope`. Names that are not parameters must be species.
+    fn expr(&self, scope: &Scope<'a>, raw: &RawExpr<'a>) -> Result<Expr, ParseError> {
+        resolve(&|fragment| self.location(fragment), raw, &|name| {
+            let name = scope.global(name);
+            match (self.params.get(&name), self.species_map.get(&name)) {
+                (Some(value), _) => Some(Expr::Param(name, *value)),
+                (None, Some(idx)) => Some(Expr::Species(*idx)),
+                (None, None) => None,
+            }
+        })
+    }
+
+    /// Create a reaction with the given rate, defaulting to 1. A number or a parameter name gives mass-action kinetics with that rate parameter; any other expression is a custom rate law, resolved when the CRN is built.
+    fn reaction(
+        &mut self,
+        scope: &Rc<Scope<'a>>,
+        reactants: HashMap<usize, f64>,
+        products: HashMap<usize, f64>,
+        rate: Option<&RawExpr<'a>>,
+    ) -> Result<Reaction, ParseError> {
+        let mut rxn = Reaction::new(reactants, products, 1.0);
+        match rate {
+            None => {}
+            Some(RawExpr::Num(num)) => rxn.rate = self.number(num)?,
+            Some(RawExpr::Ident(name)) if self.params.contains_key(&scope.global(name)) => {
+                let name = scope.global(name);
+                rxn.rate = self.params[&name];
+                rxn.rate_param = Some(name);
+            }
+            Some(raw) => self
+                .laws
+                .push((self.rxns.len(), scope.clone(), raw.clone())),
+        }
+        Ok(rxn)
+    }
+
+    /// Add a reaction to the CRN. A reversible reaction is added as a pair of reactions that refer to each other.
+    fn add_reaction(
+        &mut self,
+        scope: &Rc<Scope<'a>>,
+        tokens: &ReactionTokens<'a>,
+    ) -> Result<(), ParseError> {
+        let reactants = self.stoichiometry(scope, &tokens.reactants)?;
+        let products = self.stoichiometry(scope, &tokens.products)?;
+        let mut rates = tokens.rates.iter();
+        if tokens.reversible {
+            let idx = self.rxns.len();
+            let mut forward =
+                self.reaction(scope, reactants.clone(), products.clone(), rates.next())?;
+            forward.reverse = Some(idx + 1);
+            self.rxns.push(forward);
+            let mut reverse = self.reaction(scope, products, reactants, rates.next())?;
+            reverse.reverse = Some(idx);
+            self.rxns.push(reverse);
+        } else {
+            let rxn = self.reaction(scope, reactants, products, rates.next())?;
+            self.rxns.push(rxn);
+        }
+        Ok(())
+    }
+
+    /// Finish building the CRN.
+    fn build(mut self) -> Result<Crn<T>, ParseError> {
+        for (idx, scope, raw) in std::mem::take(&mut self.laws) {
+            self.rxns[idx].law = KineticLaw::Custom(self.expr(&scope, &raw)?);
+        }
+        for (idx, scope, lhs, rhs) in std::mem::take(&mut self.conditions) {
+            let (lhs, rhs) = (self.expr(&scope, &lhs)?, self.expr(&scope, &rhs)?);
+            if let Trigger::When(condition) = &mut self.events[idx].trigger {
+                condition.lhs = lhs;
+                condition.rhs = rhs;
+            }
+        }
+        let mut crn = Crn::from_parts(
+            self.rxns,
+            self.amounts,
+            self.names,
+            self.params,
+            self.boundary,
+        );
+        crn.init_exprs = self.init_exprs;
+        crn.events = self.events;
+        Ok(crn)
+    }
 }
 
 impl<T> Crn<T>
@@ -94,75 +1453,768 @@ where
     <T as std::str::FromStr>::Err: std::fmt::Debug,
 {
     /// Parse a CRN from a string.
+    ///
+    /// Parameters, species amounts and reactions may appear in any order, and `#`, `//` and `/* */` comments are ignored.
+    ///
+    /// A reaction's rate is a number or a parameter name for mass-action kinetics, or any other expression for a custom rate law such as `hill(A, 10, 2)`.
+    ///
+    /// A module such as `module catalysis(S, P; k) { S + E -> P + E : k; E = 1; }` is a reusable piece of a CRN, which takes species and then, after a `;`, parameters. Each instance, such as `c1: catalysis(A, B; 0.5);`, adds a copy of its statements with the arguments in place of the module's species and parameters. The instance's other species and parameters are its own, named after it: here the enzyme is the species `c1.E`. Modules can contain instances of other modules.
+    ///
+    /// `include "file.crn";` directives are rejected with [`ParseError::Unsupported`], so that parsing a string never reads files. Use [`Crn::parse_file`] for models that include others.
     pub fn parse(input: &str) -> Result<Crn<T>, ParseError> {
-        let (leftover_input, counts) = parse_counts(input).unwrap();
-        let mut species_map: HashMap<&str, usize> = HashMap::new();
-        let mut names = bimap::BiHashMap::<usize, String>::new();
-        let mut x = Vec::<T>::with_capacity(counts.len());
-        for (i, (species, num)) in counts.iter().enumerate() {
-            if species_map.contains_key(species) {
-                return Err(ParseError::DuplicateDefinition(species.to_string()));
-            } else {
-                species_map.insert(species, i);
-                names.insert(i, species.to_string());
-                x.push(num.parse::<T>().unwrap());
+        Self::parse_sources(
+            Vec::new(),
+            Vec::new(),
+            None,
+            input,
+            &mut HashMap::new(),
+            &mut VecDeque::new(),
+        )
+    }
+
+    /// Parse a CRN from a file, as [`Crn::parse`] does. `include "file.crn";` adds the statements of another file, found relative to the directory of the file that includes it. A file included more than once is only added once, and a file may not include itself. Errors give the file they occurred in.
+    pub fn parse_file(path: impl AsRef<Path>) -> Result<Crn<T>, ParseError> {
+        let path = path.as_ref();
+        let io_error = |err: std::io::Error| ParseError::Io {
+            path: path.to_path_buf(),
+            message: err.to_string(),
+        };
+        let text = fs::read_to_string(path).map_err(io_error)?;
+        let mut seen = HashMap::new();
+        seen.extend(fs::canonicalize(path).ok().map(|canonical| (canonical, 0)));
+        Self::parse_sources(
+            Vec::new(),
+            Vec::new(),
+            Some(path.to_path_buf()),
+            &text,
+            &mut seen,
+            &mut VecDeque::new(),
+        )
+    }
+
+    /// Parse `text`, read from `path` if it came from a file, as the next of `sources`, whose statements so far are `statements`. Then read and parse the next file waiting in `pending` the same way, or once none are left, build the CRN. Each file's text is kept by the call that read it until the CRN is built, so that every file is parsed only once.
+    fn parse_sources<'a>(
+        mut sources: Vec<Source<'a>>,
+        mut statements: Vec<Vec<Statement<'a>>>,
+        path: Option<PathBuf>,
+        text: &'a str,
+        seen: &mut HashMap<PathBuf, usize>,
+        pending: &mut VecDeque<(PathBuf, Location)>,
+    ) -> Result<Crn<T>, ParseError> {
+        let parsed = parse_statements(text, path.as_deref())?;
+        sources.push(Source {
+            path,
+            text,
+            includes: Vec::new(),
+        });
+        queue_includes(&mut sources, statements.len(), &parsed, seen, pending)?;
+        statements.push(parsed);
+        if let Some((path, location)) = pending.pop_front() {
+            let text = fs::read_to_string(&path).map_err(|err| ParseError::Include {
+                path: path.clone(),
+                message: err.to_string(),
+                location,
+            })?;
+            return Self::parse_sources(sources, statements, Some(path), &text, seen, pending);
+        }
+        Self::build(&sources, &statements)
+    }
+
+    /// Build a CRN from the statements of every source, starting with the first.
+    fn build<'a>(
+        sources: &'a [Source<'a>],
+        statements: &'a [Vec<Statement<'a>>],
+    ) -> Result<Crn<T>, ParseError> {
+        let mut top_level = Vec::new();
+        expand_includes(
+            statements,
+            sources,
+            0,
+            &mut Vec::new(),
+            &mut HashSet::new(),
+            &mut top_level,
+        )?;
+
+        let mut builder = Builder::new(sources);
+        let mut modules = HashMap::new();
+        for statement in &top_level {
+            if let Statement::Module(module) = statement {
+                if modules.insert(module.name, module).is_some() {
+                    return Err(ParseError::DuplicateDefinition {
+                        name: module.name.to_string(),
+                        location: builder.location(module.name),
+                    });
+                }
             }
         }
+        let mut flat = Vec::new();
+        builder.flatten(
+            &modules,
+            Rc::default(),
+            &top_level,
+            &mut Vec::new(),
+            &mut flat,
+        )?;
+        for (scope, statement) in &flat {
+            if let Statement::Param(name, num) = statement {
+                builder.add_param(scope.global(name), name, num)?;
+            }
+        }
+        for (global, arg) in std::mem::take(&mut builder.param_args) {
+            if !builder.params.contains_key(&global) {
+                return Err(ParseError::UnknownParameter {
+                    name: global,
+                    location: builder.location(arg),
+                });
+            }
+        }
+        for (scope, statement) in &flat {
+            builder.add(scope, statement)?;
+        }
+        builder.build()
+    }
+}
 
-        let (_leftover_input, reactions) = parse_reactions(leftover_input).unwrap();
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeSet, HashMap};
+    use std::fmt::Debug;
 
-        let mut rxns = Vec::<Reaction>::with_capacity(reactions.len());
+    use proptest::prelude::*;
 
-        for ((reactants, products), rate) in reactions {
-            let mut reactant_map: HashMap<usize, i32> = HashMap::new();
-            let mut product_map: HashMap<usize, i32> = HashMap::new();
+    use crate::{
+        expr::{Expr, Func},
+        parse::ParseError,
+        presets, Crn, DetCrn, KineticLaw, Reaction, StoCrn,
+    };
 
-            for (num, species) in reactants {
-                let num: i32 = if num.is_empty() {
-                    1
-                } else {
-                    num.parse().unwrap()
-                };
-                if !species_map.contains_key(species) {
-                    let len = species_map.len();
-                    species_map.insert(species, len);
-                    names.insert(len, species.to_string());
-                    x.push(T::default());
-                    reactant_map.insert(len, num);
-                } else {
-                    reactant_map.insert(species_map[species], num);
-                }
+    #[test]
+    fn presets() {
+        for preset in [
+            presets::ROCK_PAPER_SCISSORS,
+            presets::PREDATOR_PREY,
+            presets::POLYA,
+            presets::RPSLS,
+            presets::MAJORITY,
+            presets::MAJORITY_CATALYZED,
+            presets::MULTIPLY,
+            presets::MULTIPLY_CATALYZED,
+            presets::EQUILIBRIUM,
+            presets::CHAIN,
+            presets::OTHER,
+        ] {
+            StoCrn::parse(preset).unwrap();
+            DetCrn::parse(preset).unwrap();
+        }
+    }
+
+    #[test]
+    fn syntax_error_location() {
+        let err = StoCrn::parse("A = 1;\nA -> B\n").unwrap_err();
+        match err {
+            ParseError::Syntax { expected, location } => {
+                assert_eq!(expected, "`;`");
+                assert_eq!((location.line, location.column), (3, 1));
+                assert_eq!(location.snippet, "");
             }
+            e => panic!("unexpected error {e:?}"),
+        }
+    }
 
-            for (num, species) in products {
-                let num: i32 = if num.is_empty() {
-                    1
-                } else {
-                    num.parse().unwrap()
-                };
-                if !species_map.contains_key(species) {
-                    let len = species_map.len();
-                    species_map.insert(species, len);
-                    names.insert(len, species.to_string());
-                    x.push(T::default());
-                    product_map.insert(len, num);
-                } else {
-                    product_map.insert(species_map[species], num);
-                }
+    #[test]
+    fn invalid_number() {
+        let err = StoCrn::parse("A = 1;\nB = 2.5;").unwrap_err();
+        match err {
+            ParseError::InvalidNumber { text, location } => {
+                assert_eq!(text, "2.5");
+                assert_eq!((location.line, location.column), (2, 5));
+                assert_eq!(location.snippet, "2.5;");
             }
-            let rxn = Reaction::new(reactant_map, product_map, rate.unwrap_or(1.0));
-            rxns.push(rxn);
+            e => panic!("unexpected error {e:?}"),
+        }
+        assert!(DetCrn::parse("A = 1;\nB = 2.5;").is_ok());
+
+        // numbers too large to be finite could not be written back
+        for text in [
+            "A -> B : 1e400;",
+            "A -> B : 2 * 1e400 * A;",
+            "A = 1e400;",
+            "param k = 1e400;",
+            "at 1e400: A = 1;",
+        ] {
+            assert!(
+                matches!(DetCrn::parse(text), Err(ParseError::InvalidNumber { text, .. }) if text == "1e400"),
+                "{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_coefficient() {
+        let err = StoCrn::parse("A -> 99999999999B;").unwrap_err();
+        assert!(
+            matches!(err, ParseError::InvalidCoefficient { text, .. } if text == "99999999999")
+        );
+        assert!(matches!(
+            StoCrn::parse("0A -> B;"),
+            Err(ParseError::InvalidCoefficient { .. })
+        ));
+    }
+
+    #[test]
+    fn boundary() {
+        let mut crn = StoCrn::parse("$F = 100; A = 1; F + A -> 2A + F; A -> F;").unwrap();
+        assert_eq!(crn.boundary, BTreeSet::from([0]));
+        assert_eq!(crn.rxns[0].delta, HashMap::from([(1, 1.0)]));
+        assert_eq!(crn.rxns[1].delta, HashMap::from([(1, -1.0)]));
+        assert!(crn.to_string().starts_with("$F = 100;\n"));
+        let reparsed = StoCrn::parse(&crn.to_string()).unwrap();
+        assert_eq!(reparsed.boundary, crn.boundary);
+        assert_eq!(reparsed.rxns, crn.rxns);
+
+        crn.set_boundary(0, false);
+        assert_eq!(crn.rxns[0].delta, HashMap::from([(0, 0.0), (1, 1.0)]));
+        assert_eq!(crn.rxns[1].delta, HashMap::from([(0, 1.0), (1, -1.0)]));
+        assert!(crn.boundary.is_empty());
+
+        let crn = StoCrn::parse("module m() { $F = 1; F -> A; } c: m();").unwrap();
+        assert_eq!(
+            crn.boundary,
+            BTreeSet::from([*crn.names.get_by_right("c.F").unwrap()])
+        );
+    }
+
+    #[test]
+    fn modules() {
+        let mut crn = StoCrn::parse(
+            "
+            module catalysis(S, P; k) { E = 2; S + E -> P + E : k; }
+            module pathway(S, P; k) { first: catalysis(S, I; k); second: catalysis(I, P; 2); }
+            param k = 0.5;
+            A = 10;
+            c1: catalysis(A, B; k);
+            c2: catalysis(A, B; 3);
+            p: pathway(B, C; k);
+            ",
+        )
+        .unwrap();
+        let idx = |name: &str| *crn.names.get_by_right(name).unwrap();
+        assert_eq!(crn.init_state.species[idx("c1.E")], 2);
+        assert_ne!(idx("c1.E"), idx("c2.E"));
+        assert_eq!(crn.init_state.species[idx("p.first.E")], 2);
+        assert!(crn.names.contains_right("p.I"));
+        assert_eq!(crn.rxns.len(), 4);
+        assert_eq!(
+            crn.rxns[0].reactants,
+            HashMap::from([(idx("A"), 1.0), (idx("c1.E"), 1.0)])
+        );
+        assert_eq!(crn.rxns[0].rate_param.as_deref(), Some("k"));
+        assert_eq!(crn.rxns[1].rate_param.as_deref(), Some("c2.k"));
+        assert_eq!(crn.rxns[3].rate_param.as_deref(), Some("p.second.k"));
+        assert_eq!(crn.params["c2.k"], 3.0);
+
+        crn.set_parameter("k", 4.0).unwrap();
+        assert_eq!(crn.rxns[2].rate, 4.0);
+        let reparsed = StoCrn::parse(&crn.to_string()).unwrap();
+        assert_eq!(reparsed.rxns, crn.rxns);
+        assert_eq!(reparsed.names, crn.names);
+    }
+
+    #[test]
+    fn module_errors() {
+        assert!(matches!(
+            StoCrn::parse("c: missing(A);"),
+            Err(ParseError::UnknownModule { name, .. }) if name == "missing"
+        ));
+        assert!(matches!(
+            StoCrn::parse("module m(X) { inner: n(X); } module n(X) { again: m(X); } c: m(A);"),
+            Err(ParseError::RecursiveModule { name, .. }) if name == "m"
+        ));
+        assert!(matches!(
+            StoCrn::parse("module m(X; k) { X -> : k; } c: m(A, B; 1);"),
+            Err(ParseError::Syntax { expected, .. }) if expected.contains("1 species and 1 parameters")
+        ));
+        assert!(matches!(
+            StoCrn::parse("module m(X) { X ->; } c: m(A); c: m(B);"),
+            Err(ParseError::DuplicateDefinition { name, .. }) if name == "c"
+        ));
+        assert!(matches!(
+            StoCrn::parse("module m(X; k) { X -> : k; } c: m(A; k);"),
+            Err(ParseError::UnknownParameter { name, .. }) if name == "k"
+        ));
+        assert!(matches!(
+            StoCrn::parse("module m(X) { X -> Y;"),
+            Err(ParseError::Syntax { expected, .. }) if expected == "`}`"
+        ));
+        // Names that start like a module are still species.
+        let crn = StoCrn::parse("module -> A; module = 3;").unwrap();
+        assert!(crn.names.contains_right("module"));
+    }
+
+    /// Writes `files` to a new temporary directory, and returns its path.
+    fn write_files(test: &str, files: &[(&str, &str)]) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("crn-{test}-{}", std::process::id()));
+        for (name, text) in files {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, text).unwrap();
         }
+        dir
+    }
+
+    #[test]
+    fn includes() {
+        let dir = write_files(
+            "includes",
+            &[
+                (
+                    "main.crn",
+                    "include \"parts/core.crn\";\ninclude \"leak.crn\";\nA = 10;",
+                ),
+                (
+                    "parts/core.crn",
+                    "include \"../leak.crn\";\nparam k = 2;\nA -> B : k;",
+                ),
+                ("leak.crn", "// shared by both files\nB ->;"),
+                ("cycle.crn", "include \"parts/back.crn\";"),
+                ("parts/back.crn", "A -> B;\ninclude \"../cycle.crn\";"),
+                ("broken.crn", "include \"parts/bad.crn\";"),
+                ("parts/bad.crn", "A -> B;\nA -> ?;"),
+                ("dangling.crn", "A ->;\ninclude \"nowhere/missing.crn\";"),
+            ],
+        );
+        let crn = StoCrn::parse_file(dir.join("main.crn")).unwrap();
+        assert_eq!(crn.rxns.len(), 2);
+        // The leak comes first, from the first file to include it.
+        assert!(crn.rxns[0].products.is_empty());
+        assert_eq!(crn.rxns[1].rate_param.as_deref(), Some("k"));
+        assert_eq!(crn.init_state.species, vec![0, 10]);
+
+        let err = StoCrn::parse_file(dir.join("cycle.crn")).unwrap_err();
+        assert!(
+            matches!(&err, ParseError::IncludeCycle { path, .. } if path.ends_with("cycle.crn"))
+        );
+        assert_eq!(err.location().unwrap().line, 2);
+
+        let err = StoCrn::parse_file(dir.join("broken.crn")).unwrap_err();
+        let location = err.location().unwrap();
+        assert_eq!(location.line, 2);
+        assert!(location.file.as_ref().unwrap().ends_with("parts/bad.crn"));
+        assert!(err.to_string().contains("bad.crn, line 2"));
+
+        let crn = StoCrn::parse("include = 3; A -> include;").unwrap();
+        assert_eq!(crn.init_state.species, vec![3, 0]);
+        let reparsed = StoCrn::parse(&crn.to_string()).unwrap();
+        assert_eq!(reparsed.names, crn.names);
 
-        let state = State {
-            species: x,
-            time: 0.0,
+        let err = StoCrn::parse_file(dir.join("missing.crn")).unwrap_err();
+        assert!(matches!(err, ParseError::Io { .. }));
+        let err = StoCrn::parse_file(dir.join("dangling.crn")).unwrap_err();
+        assert!(
+            matches!(err, ParseError::Include { location, .. } if location.line == 2 && location.file.is_some())
+        );
+        // parsing a string never reads files
+        let err = StoCrn::parse("A ->;\ninclude \"leak.crn\";").unwrap_err();
+        assert!(
+            matches!(err, ParseError::Unsupported { location, .. } if location.line == 2 && location.file.is_none())
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn fractional_coefficients() {
+        let err = StoCrn::parse(
+            "A = 4;
+0.5A -> B;",
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ParseError::FractionalCoefficient { text, location } if text == "0.5" && location.line == 2
+        ));
+        assert!(StoCrn::parse("A = 4; 2.0A -> B;").is_ok());
+
+        let mut crn =
+            DetCrn::parse("H2 = 2; O2 = 1; 2 H2 + O2 -> 2 H2O : 1; H2O -> H2 + 0.5 O2 : 0;")
+                .unwrap();
+        assert_eq!(crn.rxns[1].products[&1], 0.5);
+        assert!(crn.to_string().contains("0.5O2"));
+        let reparsed = DetCrn::parse(&crn.to_string()).unwrap();
+        assert_eq!(reparsed.rxns, crn.rxns);
+
+        crn.simulate_history(1.0, 0.001).unwrap();
+        let [h2, o2, h2o] = crn.state.species[..] else {
+            panic!("three species")
         };
-        Ok(Self {
-            init_state: state.clone(),
-            rxns,
-            state,
+        assert!((h2 + h2o - 2.0).abs() < 1e-6);
+        assert!((2.0 * o2 + h2o - 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn trailing_input() {
+        let err = StoCrn::parse("A = 1; A -> B;\n  B -> ?;").unwrap_err();
+        match err {
+            ParseError::Syntax { location, .. } => assert_eq!(location.snippet, "?;"),
+            e => panic!("unexpected error {e:?}"),
+        }
+        let err = StoCrn::parse("A = 1; A -> B;\n  ???").unwrap_err();
+        match err {
+            ParseError::TrailingInput { location } => {
+                assert_eq!((location.line, location.column), (2, 3));
+                assert_eq!(location.snippet, "???");
+            }
+            e => panic!("unexpected error {e:?}"),
+        }
+    }
+
+    #[test]
+    fn comments_and_any_order() {
+        let crn = StoCrn::parse(
+            "# predator and prey
+            a + b -> 2b : 0.005; // predation
+            /* growth
+               and death */
+            a -> 2a;
+            a = 100; b = 100;
+            b -> ; # done",
+        )
+        .unwrap();
+        assert_eq!(crn.rxns.len(), 3);
+        assert_eq!(crn.init_state.species, vec![100, 100]);
+        assert_eq!(crn.names.get_by_left(&0).unwrap(), "a");
+
+        let err = StoCrn::parse("a -> b; /* unterminated").unwrap_err();
+        assert!(matches!(err, ParseError::Syntax { expected, .. } if expected == "`*/`"));
+    }
+
+    #[test]
+    fn display_roundtrip() {
+        let mut crn = StoCrn::parse(presets::OTHER).unwrap();
+        crn.steps(10).unwrap();
+        let reparsed = StoCrn::parse(&crn.to_string()).unwrap();
+        assert_eq!(reparsed.init_state, crn.init_state);
+        assert_eq!(reparsed.rxns, crn.rxns);
+        assert_eq!(crn.to_string(), reparsed.to_string());
+
+        let crn = DetCrn::parse("A = 0.1; A -> B : 1e-9; B -> : 12345678912345678;").unwrap();
+        assert_eq!(
+            crn.to_string(),
+            "A = 0.1;\nB = 0;\nA -> B : 1e-9;\nB ->  : 1.2345678912345678e16;\n"
+        );
+
+        let mut crn = StoCrn::parse("A = 1; A -> B;").unwrap();
+        crn.names.remove_by_left(&0);
+        crn.names.insert(2, "s0".to_string());
+        crn.init_state.species.push(3);
+        let reparsed = StoCrn::parse(&crn.to_string()).unwrap();
+        assert_eq!(reparsed.init_state, crn.init_state);
+        assert_eq!(reparsed.names.get_by_left(&0).unwrap(), "s1");
+    }
+
+    #[test]
+    fn reversible() {
+        let crn = StoCrn::parse("A + 2B <-> 4C + 3D : 2, 0.5; C -> D;").unwrap();
+        assert_eq!(crn.rxns.len(), 3);
+        assert_eq!(crn.rxns[0].rate, 2.0);
+        assert_eq!(crn.rxns[1].rate, 0.5);
+        assert_eq!(crn.rxns[0].reactants, crn.rxns[1].products);
+        assert_eq!(crn.rxns[0].products, crn.rxns[1].reactants);
+        assert_eq!(crn.reverse_of(0), Some(1));
+        assert_eq!(crn.reverse_of(1), Some(0));
+        assert_eq!(crn.reverse_of(2), None);
+        assert_eq!(crn.reversible_pairs().collect::<Vec<_>>(), vec![(0, 1)]);
+        assert!(crn.to_string().contains("A + 2B <-> 4C + 3D : 2, 0.5;"));
+        assert_eq!(StoCrn::parse(&crn.to_string()).unwrap().rxns, crn.rxns);
+
+        let crn = StoCrn::parse("A <-> B;").unwrap();
+        assert_eq!((crn.rxns[0].rate, crn.rxns[1].rate), (1.0, 1.0));
+        assert!(matches!(
+            StoCrn::parse("A <-> B : 1;"),
+            Err(ParseError::Syntax { expected, .. }) if expected == "`,` and a reverse rate"
+        ));
+    }
+
+    #[test]
+    fn parameters() {
+        let mut crn = DetCrn::parse(
+            "a + b -> 2b : k1;
+            param k1 = 0.005;
+            param k2 = 2;
+            a <-> c : k2, 3;
+            param = 4;",
+        )
+        .unwrap();
+        assert_eq!(crn.params["k1"], 0.005);
+        assert_eq!(crn.rxns[0].rate, 0.005);
+        assert_eq!(crn.rxns[1].rate, 2.0);
+        assert_eq!(crn.names.get_by_right("param"), Some(&3));
+
+        assert_eq!(crn.set_parameter("k1", 0.01).unwrap(), 0.005);
+        assert_eq!(crn.rxns[0].rate, 0.01);
+        assert_eq!(crn.rxns[1].rate, 2.0);
+        crn.set_parameter("k2", 5.0).unwrap();
+        assert_eq!((crn.rxns[1].rate, crn.rxns[2].rate), (5.0, 3.0));
+        assert!(matches!(
+            crn.set_parameter("k3", 1.0),
+            Err(crate::ParamError::UnknownParameter(name)) if name == "k3"
+        ));
+
+        let reparsed = DetCrn::parse(&crn.to_string()).unwrap();
+        assert_eq!(reparsed.rxns, crn.rxns);
+        assert_eq!(reparsed.params, crn.params);
+
+        assert!(matches!(
+            DetCrn::parse("a -> b : k;"),
+            Err(ParseError::UnknownParameter { name, .. }) if name == "k"
+        ));
+        assert!(matches!(
+            DetCrn::parse("param k = 1; k -> b;"),
+            Err(ParseError::DuplicateDefinition { name, .. }) if name == "k"
+        ));
+    }
+
+    #[test]
+    fn kinetic_laws() {
+        let crn = DetCrn::parse(
+            "param vmax = 2;
+            A = 10; S = 1;
+            -> P : hill(A, 10, 2);
+            S -> P : mm(S, vmax, 1);
+            A -> : 2 * k * A^2 - -1;
+            param k = 0.5;",
+        )
+        .unwrap();
+        assert_eq!(
+            crn.rxns[0].law.clone(),
+            KineticLaw::Custom(Expr::Call(
+                Func::Hill,
+                vec![Expr::Species(0), Expr::Num(10.0), Expr::Num(2.0)],
+            ))
+        );
+        assert_eq!(crn.state.rate(&crn.rxns[0]), 0.5);
+        assert_eq!(crn.state.rate(&crn.rxns[1]), 1.0);
+        assert_eq!(crn.state.rate(&crn.rxns[2]), 101.0);
+
+        let mut reparsed = DetCrn::parse(&crn.to_string()).unwrap();
+        assert_eq!(reparsed.rxns, crn.rxns);
+        reparsed.set_parameter("vmax", 4.0).unwrap();
+        assert_eq!(reparsed.state.rate(&reparsed.rxns[1]), 2.0);
+
+        let sto = StoCrn::parse("A = 10; A -> : hill(A, 10, 2); B -> : B;").unwrap();
+        assert_eq!(sto.state.rate(&sto.rxns[0]), 0.5);
+        assert_eq!(sto.state.rate(&sto.rxns[1]), 0.0);
+
+        assert!(matches!(
+            DetCrn::parse("-> A : hill(A, 2);"),
+            Err(ParseError::Syntax { expected, .. }) if expected == "3 arguments to `hill`"
+        ));
+        assert!(matches!(
+            DetCrn::parse("-> A : foo(A);"),
+            Err(ParseError::UnknownFunction { name, .. }) if name == "foo"
+        ));
+        assert!(matches!(
+            DetCrn::parse("-> A : 2 * B;"),
+            Err(ParseError::UnknownParameter { name, .. }) if name == "B"
+        ));
+    }
+
+    #[test]
+    fn count_expressions() {
+        let mut crn = StoCrn::parse(
+            "param N = 100;
+            A = 2 * N; B = N - 5; C = -3;
+            2A + B -> 3A;",
+        )
+        .unwrap();
+        assert_eq!(crn.init_state.species, vec![200, 95, -3]);
+        assert_eq!(crn.init_exprs.len(), 2);
+
+        crn.steps(5).unwrap();
+        crn.set_parameter("N", 1000.0).unwrap();
+        assert_eq!(crn.init_state.species, vec![2000, 995, -3]);
+        crn.reset();
+        assert_eq!(crn.state.species, vec![2000, 995, -3]);
+
+        assert!(crn.to_string().contains("A = 2 * N;\nB = N - 5;\nC = -3;"));
+        let reparsed = StoCrn::parse(&crn.to_string()).unwrap();
+        assert_eq!(reparsed.init_state, crn.init_state);
+        assert_eq!(reparsed.init_exprs, crn.init_exprs);
+
+        assert!(matches!(
+            crn.set_parameter("N", 2.5),
+            Err(crate::ParamError::InvalidAmount(species, amount)) if species == "B" && amount == -2.5
+        ));
+        assert_eq!(crn.params["N"], 1000.0);
+        assert_eq!(crn.init_state.species, vec![2000, 995, -3]);
+        assert!(matches!(
+            StoCrn::parse("param N = 3; A = N / 2;"),
+            Err(ParseError::InvalidNumber { text, .. }) if text == "1.5"
+        ));
+    }
+
+    #[test]
+    fn identifiers() {
+        let crn = StoCrn::parse(
+            "X_1 = 1; _y = 2;
+            param k_1 = 2;
+            mRNA_lacI -> \"ATP:Mg\" + A';
+            A' + A'' -> : k_1 * \"ATP:Mg\";
+            param_x = 3;",
+        )
+        .unwrap();
+        let names: Vec<_> = (0..7).map(|i| crn.names.get_by_left(&i).unwrap()).collect();
+        assert_eq!(
             names,
+            ["X_1", "_y", "mRNA_lacI", "ATP:Mg", "A'", "A''", "param_x"]
+        );
+        assert!(crn.params.contains_key("k_1"));
+
+        let text = crn.to_string();
+        assert!(text.contains("mRNA_lacI -> \"ATP:Mg\" + A' : 1;"));
+        assert!(text.contains(": k_1 * \"ATP:Mg\";"));
+        let reparsed = StoCrn::parse(&text).unwrap();
+        assert_eq!(reparsed.names, crn.names);
+        assert_eq!(reparsed.rxns, crn.rxns);
+
+        assert!(matches!(
+            StoCrn::parse("\"A -> B;"),
+            Err(ParseError::Syntax { expected, .. }) if expected == "closing `\"`"
+        ));
+        assert!(matches!(
+            StoCrn::parse("\"\" = 1;"),
+            Err(ParseError::Syntax { expected, .. }) if expected == "name"
+        ));
+
+        // names with quotes, backslashes and line breaks are escaped
+        let crn = StoCrn::parse(r#""say \"hi\"" = 1; "C:\\dir\nB" -> "say \"hi\"";"#).unwrap();
+        assert_eq!(crn.names.get_by_left(&0).unwrap(), "say \"hi\"");
+        assert_eq!(crn.names.get_by_left(&1).unwrap(), "C:\\dir\nB");
+        let text = crn.to_string();
+        assert!(text.contains(r#""C:\\dir\nB" -> "say \"hi\"" : 1;"#));
+        let reparsed = StoCrn::parse(&text).unwrap();
+        assert_eq!(reparsed.names, crn.names);
+        assert!(matches!(
+            StoCrn::parse(r#""a\b" = 1;"#),
+            Err(ParseError::Syntax { expected, .. }) if expected == "closing `\"`"
+        ));
+    }
+
+    #[test]
+    fn duplicate_definition() {
+        assert!(matches!(
+            StoCrn::parse("A = 1; A = 2;"),
+            Err(ParseError::DuplicateDefinition { name, .. }) if name == "A"
+        ));
+    }
+
+    /// How a generated reaction's rate is given.
+    #[derive(Clone, Debug)]
+    enum Rate {
+        /// A number.
+        Num(f64),
+        /// The parameter with this index, wrapping around.
+        Param(usize),
+        /// A Hill function of a species and two numbers.
+        Hill(usize, f64, f64),
+    }
+
+    /// Random networks of up to five species, with parameters, reversible pairs and custom rate laws.
+    fn arb_crn<T: Clone + Debug>(
+        amount: impl Strategy<Value = T> + Clone,
+    ) -> impl Strategy<Value = Crn<T>> {
+        let rate = || {
+            let num = || prop::num::f64::POSITIVE | prop::num::f64::NORMAL;
+            prop_oneof![
+                num().prop_map(Rate::Num),
+                any::<usize>().prop_map(Rate::Param),
+                (any::<usize>(), num(), num()).prop_map(|(s, k, n)| Rate::Hill(s, k, n)),
+            ]
+        };
+        (1..=5usize).prop_flat_map(move |n| {
+            let side = || prop::collection::hash_map(0..n, (1..4i32).prop_map(f64::from), 0..3);
+            (
+                prop::collection::hash_set(
+                    prop_oneof!["[A-Z_][a-z0-9_]{0,3}'{0,2}", "[ -jl-~\n\r]{1,5}"],
+                    n,
+                )
+                .prop_map(Vec::from_iter)
+                .prop_shuffle(),
+                prop::collection::vec(amount.clone(), n),
+                prop::collection::btree_map(
+                    "k[0-9]{1,2}",
+                    prop::num::f64::POSITIVE | prop::num::f64::NORMAL,
+                    0..3,
+                ),
+                prop::collection::vec((side(), side(), rate(), prop::option::of(rate())), 0..6),
+            )
+                .prop_map(|(names, amounts, params, reactions)| {
+                    let make = |reactants: &HashMap<usize, f64>,
+                                products: &HashMap<usize, f64>,
+                                rate| {
+                        let mut rxn = Reaction::new(reactants.clone(), products.clone(), 1.0);
+                        match rate {
+                            Rate::Num(x) => rxn.rate = x,
+                            Rate::Param(i) if !params.is_empty() => {
+                                let (name, value) = params.iter().nth(i % params.len()).unwrap();
+                                rxn.rate = *value;
+                                rxn.rate_param = Some(name.clone());
+                            }
+                            Rate::Param(_) => {}
+                            Rate::Hill(s, k, n) => {
+                                rxn.law = KineticLaw::Custom(Expr::Call(
+                                    Func::Hill,
+                                    vec![
+                                        Expr::Species(s % names.len()),
+                                        Expr::Num(k),
+                                        Expr::Num(n),
+                                    ],
+                                ))
+                            }
+                        }
+                        rxn
+                    };
+                    let mut rxns = Vec::new();
+                    for (reactants, products, rate, reverse) in reactions {
+                        let idx = rxns.len();
+                        let mut rxn = make(&reactants, &products, rate);
+                        if let Some(reverse) = reverse {
+                            rxn.reverse = Some(idx + 1);
+                            rxns.push(rxn);
+                            let mut rxn = make(&products, &reactants, reverse);
+                            rxn.reverse = Some(idx);
+                            rxns.push(rxn);
+                        } else {
+                            rxns.push(rxn);
+                        }
+                    }
+                    Crn::from_parts(
+                        rxns,
+                        amounts,
+                        names.into_iter().enumerate().collect(),
+                        params,
+                        [],
+                    )
+                })
         })
     }
+
+    proptest! {
+        #[test]
+        fn display_parse_roundtrip_sto(crn in arb_crn(any::<i32>())) {
+            let reparsed = StoCrn::parse(&crn.to_string()).unwrap();
+            prop_assert_eq!(&reparsed.init_state, &crn.init_state);
+            prop_assert_eq!(&reparsed.rxns, &crn.rxns);
+            prop_assert_eq!(&reparsed.names, &crn.names);
+            prop_assert_eq!(&reparsed.params, &crn.params);
+        }
+
+        #[test]
+        fn display_parse_roundtrip_det(crn in arb_crn(prop::num::f64::NORMAL | prop::num::f64::ZERO)) {
+            let reparsed = DetCrn::parse(&crn.to_string()).unwrap();
+            prop_assert_eq!(&reparsed.init_state, &crn.init_state);
+            prop_assert_eq!(&reparsed.rxns, &crn.rxns);
+            prop_assert_eq!(&reparsed.names, &crn.names);
+            prop_assert_eq!(&reparsed.params, &crn.params);
+        }
+    }
 }
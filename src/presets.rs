@@ -84,8 +84,7 @@ pub const EQUILIBRIUM: &str = "
     B = 10000;
     C = 10000;
     D = 10000;
-    A + 2B -> 4C + 3D;
-    4C + 3D -> A + 2B;
+    A + 2B <-> 4C + 3D;
     ";
 /// Looks cool.
 pub const CHAIN: &str = "
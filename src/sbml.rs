@@ -0,0 +1,771 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{Display, Write};
+use std::str::FromStr;
+
+use itertools::Itertools;
+use roxmltree::{Document, Node};
+
+use crate::{
+    expr::{BinOp, Expr, Func},
+    parse::{check_coefficient, Location, ParseError},
+    Crn, ExportError, KineticLaw, Reaction,
+};
+
+/// Namespace of SBML Level 3 Version 2 documents.
+const SBML_NS: &str = "http://www.sbml.org/sbml/level3/version2/core";
+
+/// Namespace of MathML, which SBML uses for kinetic laws.
+const MATHML_NS: &str = "http://www.w3.org/1998/Math/MathML";
+
+/// Model components that have no counterpart in a CRN.
+const UNSUPPORTED: [(&str, &str); 5] = [
+    ("listOfFunctionDefinitions", "function definitions"),
+    ("listOfInitialAssignments", "initial assignments"),
+    ("listOfRules", "rules"),
+    ("listOfConstraints", "constraints"),
+    ("listOfEvents", "events"),
+];
+
+/// Iterates over the elements called `item` inside the child elements called `list`.
+fn list_of<'a, 'input: 'a>(
+    node: Node<'a, 'input>,
+    list: &'a str,
+    item: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children()
+        .filter(move |n| n.has_tag_name(list))
+        .flat_map(move |n| n.children().filter(move |c| c.has_tag_name(item)))
+}
+
+/// Converts an SBML document into the parts of a CRN.
+struct Importer<'a> {
+    /// The document text, used to locate errors.
+    xml: &'a str,
+    /// Size of each compartment, by id.
+    compartments: HashMap<&'a str, f64>,
+    /// Index of each species, by id.
+    species: HashMap<&'a str, usize>,
+    /// The size of the compartment of each species that rate laws refer to by concentration, by index. Species in compartments of unit size are left out.
+    concentrations: HashMap<usize, f64>,
+    /// Global parameters.
+    params: BTreeMap<String, f64>,
+    /// Checks a stoichiometric coefficient against the CRN's amount type.
+    coefficient: fn(f64, &str, Location) -> Result<f64, ParseError>,
+}
+
+impl<'a> Importer<'a> {
+    /// Locates `node` in the document.
+    fn location(&self, node: Node) -> Location {
+        Location::new(self.xml, &self.xml[node.range().start..])
+    }
+
+    /// An error for a construct that cannot be represented as a CRN.
+    fn unsupported(&self, node: Node, construct: impl Into<String>) -> ParseError {
+        ParseError::Unsupported {
+            construct: construct.into(),
+            location: self.location(node),
+        }
+    }
+
+    /// Reads a required attribute.
+    fn attr<'n>(&self, node: Node<'n, 'a>, name: &str) -> Result<&'n str, ParseError> {
+        node.attribute(name).ok_or_else(|| ParseError::Syntax {
+            expected: format!("`{}` attribute on <{}>", name, node.tag_name().name()),
+            location: self.location(node),
+        })
+    }
+
+    /// Reads an optional numeric attribute.
+    fn num_attr(&self, node: Node, name: &str) -> Result<Option<f64>, ParseError> {
+        node.attribute(name)
+            .map(|text| {
+                text.trim().parse().map_err(|_| ParseError::InvalidNumber {
+                    text: text.to_string(),
+                    location: self.location(node),
+                })
+            })
+            .transpose()
+    }
+
+    /// Reads the stoichiometry of the species references in `list`.
+    fn stoichiometry(&self, reaction: Node, list: &str) -> Result<HashMap<usize, f64>, ParseError> {
+        let mut result = HashMap::new();
+        for reference in list_of(reaction, list, "speciesReference") {
+            let id = self.attr(reference, "species")?;
+            let idx = *self
+                .species
+                .get(id)
+                .ok_or_else(|| ParseError::UnknownParameter {
+                    name: id.to_string(),
+                    location: self.location(reference),
+                })?;
+            let coefficient = self.num_attr(reference, "stoichiometry")?.unwrap_or(1.0);
+            let coefficient = (self.coefficient)(
+                coefficient,
+                &coefficient.to_string(),
+                self.location(reference),
+            )?;
+            *result.entry(idx).or_insert(0.0) += coefficient;
+        }
+        Ok(result)
+    }
+
+    /// Converts a MathML element into an expression. `locals` are the reaction's local parameters.
+    fn math(&self, node: Node, locals: &HashMap<&str, f64>) -> Result<Expr, ParseError> {
+        match node.tag_name().name() {
+            "math" => match node.children().find(|n| n.is_element()) {
+                Some(child) => self.math(child, locals),
+                None => Err(ParseError::Syntax {
+                    expected: "a MathML expression".to_string(),
+                    location: self.location(node),
+                }),
+            },
+            "ci" => {
+                let name = node.text().unwrap_or_default().trim();
+                if let Some(value) = locals.get(name) {
+                    Ok(Expr::Num(*value))
+                } else if let Some(idx) = self.species.get(name) {
+                    Ok(match self.concentrations.get(idx) {
+                        Some(size) => Expr::BinOp(
+                            BinOp::Div,
+                            Box::new(Expr::Species(*idx)),
+                            Box::new(Expr::Num(*size)),
+                        ),
+                        None => Expr::Species(*idx),
+                    })
+                } else if let Some(value) = self.params.get(name) {
+                    Ok(Expr::Param(name.to_string(), *value))
+                } else if let Some(size) = self.compartments.get(name) {
+                    Ok(Expr::Num(*size))
+                } else {
+                    Err(ParseError::UnknownParameter {
+                        name: name.to_string(),
+                        location: self.location(node),
+                    })
+                }
+            }
+            "cn" => match node.attribute("type") {
+                None | Some("real") | Some("integer") => {
+                    let text = node.text().unwrap_or_default().trim();
+                    text.parse()
+                        .map(Expr::Num)
+                        .map_err(|_| ParseError::InvalidNumber {
+                            text: text.to_string(),
+                            location: self.location(node),
+                        })
+                }
+                Some(other) => Err(self.unsupported(node, format!("`{other}` numbers"))),
+            },
+            "apply" => {
+                let mut children = node.children().filter(|n| n.is_element());
+                let Some(op) = children.next() else {
+                    return Err(ParseError::Syntax {
+                        expected: "a MathML operator".to_string(),
+                        location: self.location(node),
+                    });
+                };
+                let args = children
+                    .map(|arg| self.math(arg, locals))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let arity = |n: usize| {
+                    if args.len() == n {
+                        Ok(())
+                    } else {
+                        Err(ParseError::Syntax {
+                            expected: format!("{} arguments to <{}>", n, op.tag_name().name()),
+                            location: self.location(op),
+                        })
+                    }
+                };
+                let fold = |op: BinOp, empty: f64, args: Vec<Expr>| {
+                    args.into_iter()
+                        .reduce(|a, b| Expr::BinOp(op, Box::new(a), Box::new(b)))
+                        .unwrap_or(Expr::Num(empty))
+                };
+                let binary = |op: BinOp, mut args: Vec<Expr>| {
+                    let b = args.pop().unwrap();
+                    let a = args.pop().unwrap();
+                    Expr::BinOp(op, Box::new(a), Box::new(b))
+                };
+                match op.tag_name().name() {
+                    "plus" => Ok(fold(BinOp::Add, 0.0, args)),
+                    "times" => Ok(fold(BinOp::Mul, 1.0, args)),
+                    "minus" if args.len() == 1 => Ok(match args.into_iter().next().unwrap() {
+                        Expr::Num(x) => Expr::Num(-x),
+                        e => Expr::Neg(Box::new(e)),
+                    }),
+                    "minus" => arity(2).map(|_| binary(BinOp::Sub, args)),
+                    "divide" => arity(2).map(|_| binary(BinOp::Div, args)),
+                    "power" => arity(2).map(|_| binary(BinOp::Pow, args)),
+                    "exp" => arity(1).map(|_| Expr::Call(Func::Exp, args)),
+                    "ln" => arity(1).map(|_| Expr::Call(Func::Ln, args)),
+                    other => Err(self.unsupported(op, format!("the MathML operator <{other}>"))),
+                }
+            }
+            other => Err(self.unsupported(node, format!("the MathML element <{other}>"))),
+        }
+    }
+
+    /// Converts a reaction into one reaction, or two for a reversible reaction.
+    fn reaction(&self, node: Node) -> Result<Vec<Reaction>, ParseError> {
+        if node.attribute("fast") == Some("true") {
+            return Err(self.unsupported(node, "fast reactions"));
+        }
+        let reactants = self.stoichiometry(node, "listOfReactants")?;
+        let products = self.stoichiometry(node, "listOfProducts")?;
+
+        let law = node
+            .children()
+            .find(|n| n.has_tag_name("kineticLaw"))
+            .ok_or_else(|| ParseError::Syntax {
+                expected: "<kineticLaw>".to_string(),
+                location: self.location(node),
+            })?;
+        let mut locals = HashMap::new();
+        for local in list_of(law, "listOfLocalParameters", "localParameter") {
+            locals.insert(
+                self.attr(local, "id")?,
+                self.num_attr(local, "value")?.unwrap_or(0.0),
+            );
+        }
+        let math = law
+            .children()
+            .find(|n| n.has_tag_name("math"))
+            .ok_or_else(|| ParseError::Syntax {
+                expected: "<math>".to_string(),
+                location: self.location(law),
+            })?;
+        let expr = self.math(math, &locals)?;
+
+        if node.attribute("reversible") == Some("true") {
+            match Reaction::pair_from_law(&expr, &reactants, &products) {
+                Some(pair) => Ok(pair.to_vec()),
+                None => Err(self.unsupported(
+                    law,
+                    "reversible reactions whose rate law is not a difference of mass-action terms",
+                )),
+            }
+        } else {
+            Ok(vec![Reaction::with_law(reactants, products, expr)])
+        }
+    }
+}
+
+impl<T> Crn<T>
+where
+    T: Default + Clone + FromStr,
+{
+    /// Reads a CRN from an SBML Level 3 document.
+    ///
+    /// Species, including boundary species, initial amounts and concentrations, compartments, global and local parameters, and reactions are supported. Rate laws of the form `k * A * B^2`, with a single parameter or only numbers as the rate, become mass-action reactions, and a reversible reaction whose rate law is the difference of two such terms becomes a reversible pair. Other rate laws become custom kinetic laws. Amounts are always counted in substance units; where a species without `hasOnlySubstanceUnits` appears in a rate law, it stands for its concentration, its amount divided by the size of its compartment. Rules, events, and other constructs with no counterpart in a CRN are rejected with [`ParseError::Unsupported`]. Species are named by their `name`, or their `id` if it has none or the name is taken; a species or parameter whose name is already in use is rejected with [`ParseError::DuplicateDefinition`].
+    pub fn from_sbml(xml: &str) -> Result<Crn<T>, ParseError> {
+        let doc = Document::parse(xml).map_err(|e| {
+            let pos = e.pos();
+            let line_start: usize = xml
+                .split_inclusive('\n')
+                .take(pos.row as usize - 1)
+                .map(str::len)
+                .sum();
+            let line = &xml[line_start.min(xml.len())..];
+            let column: usize = line
+                .chars()
+                .take(pos.col as usize - 1)
+                .map(char::len_utf8)
+                .sum();
+            ParseError::Syntax {
+                expected: format!("well-formed XML ({e})"),
+                location: Location::new(xml, &line[column.min(line.len())..]),
+            }
+        })?;
+
+        let mut importer = Importer {
+            xml,
+            compartments: HashMap::new(),
+            species: HashMap::new(),
+            concentrations: HashMap::new(),
+            params: BTreeMap::new(),
+            coefficient: check_coefficient::<T>,
+        };
+
+        let root = doc.root_element();
+        if !root.has_tag_name("sbml") {
+            return Err(ParseError::Syntax {
+                expected: "<sbml>".to_string(),
+                location: importer.location(root),
+            });
+        }
+        if let Some(level) = root.attribute("level").filter(|level| *level != "3") {
+            return Err(importer.unsupported(root, format!("SBML level {level}")));
+        }
+        let model = root
+            .children()
+            .find(|n| n.has_tag_name("model"))
+            .ok_or_else(|| ParseError::Syntax {
+                expected: "<model>".to_string(),
+                location: importer.location(root),
+            })?;
+        for (list, construct) in UNSUPPORTED {
+            if let Some(node) = model
+                .children()
+                .find(|n| n.has_tag_name(list) && n.children().any(|c| c.is_element()))
+            {
+                return Err(importer.unsupported(node, construct));
+            }
+        }
+
+        for compartment in list_of(model, "listOfCompartments", "compartment") {
+            let size = importer.num_attr(compartment, "size")?.unwrap_or(1.0);
+            importer
+                .compartments
+                .insert(importer.attr(compartment, "id")?, size);
+        }
+
+        let mut names = bimap::BiHashMap::new();
+        let mut amounts = Vec::new();
+        let mut boundary = Vec::new();
+        for species in list_of(model, "listOfSpecies", "species") {
+            let id = importer.attr(species, "id")?;
+            if species.attribute("boundaryCondition") == Some("true") {
+                boundary.push(importer.species.len());
+            } else if species.attribute("constant") == Some("true") {
+                return Err(importer.unsupported(species, "constant species"));
+            }
+            let size = match species.attribute("compartment") {
+                Some(compartment) => *importer.compartments.get(compartment).unwrap_or(&1.0),
+                None => 1.0,
+            };
+            if species.attribute("hasOnlySubstanceUnits") != Some("true") && size != 1.0 {
+                importer.concentrations.insert(importer.species.len(), size);
+            }
+            let amount = match (
+                importer.num_attr(species, "initialAmount")?,
+                importer.num_attr(species, "initialConcentration")?,
+            ) {
+                (Some(amount), _) => amount,
+                (None, Some(concentration)) => concentration * size,
+                (None, None) => 0.0,
+            };
+            let amount = amount.to_string();
+            amounts.push(amount.parse::<T>().map_err(|_| ParseError::InvalidNumber {
+                text: amount,
+                location: importer.location(species),
+            })?);
+
+            let idx = importer.species.len();
+            if importer.species.insert(id, idx).is_some() {
+                return Err(ParseError::DuplicateDefinition {
+                    name: id.to_string(),
+                    location: importer.location(species),
+                });
+            }
+            // species are named by their `name`, or by their `id` if the name is missing or already taken
+            let name = species
+                .attribute("name")
+                .filter(|name| !name.is_empty() && !names.contains_right(*name))
+                .unwrap_or(id);
+            if names.contains_right(name) {
+                return Err(ParseError::DuplicateDefinition {
+                    name: name.to_string(),
+                    location: importer.location(species),
+                });
+            }
+            names.insert(idx, name.to_string());
+        }
+
+        for param in list_of(model, "listOfParameters", "parameter") {
+            let value = importer.num_attr(param, "value")?.unwrap_or(0.0);
+            let id = importer.attr(param, "id")?;
+            if names.contains_right(id) {
+                return Err(ParseError::DuplicateDefinition {
+                    name: id.to_string(),
+                    location: importer.location(param),
+                });
+            }
+            importer.params.insert(id.to_string(), value);
+        }
+
+        let mut rxns = Vec::new();
+        for reaction in list_of(model, "listOfReactions", "reaction") {
+            let idx = rxns.len();
+            let mut new = importer.reaction(reaction)?;
+            if let [forward, reverse] = &mut new[..] {
+                forward.reverse = Some(idx + 1);
+                reverse.reverse = Some(idx);
+            }
+            rxns.extend(new);
+        }
+
+        Ok(Crn::from_parts(
+            rxns,
+            amounts,
+            names,
+            importer.params,
+            boundary,
+        ))
+    }
+}
+
+/// Escapes text for use in an XML attribute or element.
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Chooses SBML identifiers, which must be unique and consist of letters, digits and underscores.
+#[derive(Default)]
+pub(crate) struct Ids {
+    /// Identifiers already in use.
+    taken: HashSet<String>,
+}
+
+impl Ids {
+    /// Creates an allocator that never hands out any of `reserved`.
+    pub(crate) fn reserving(reserved: &[&str]) -> Self {
+        Self {
+            taken: reserved.iter().map(|word| word.to_string()).collect(),
+        }
+    }
+
+    /// Returns an unused identifier based on `name`.
+    pub(crate) fn allocate(&mut self, name: &str) -> String {
+        let mut id: String = name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        if !id.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+            id.insert(0, '_');
+        }
+        let mut candidate = id.clone();
+        let mut i = 1;
+        while self.taken.contains(&candidate) {
+            candidate = format!("{id}_{i}");
+            i += 1;
+        }
+        self.taken.insert(candidate.clone());
+        candidate
+    }
+}
+
+/// The SBML identifiers chosen for a CRN's species and parameters.
+struct IdMap {
+    /// The identifier of each species, by index.
+    species: Vec<String>,
+    /// The identifier of each parameter, by name.
+    params: HashMap<String, String>,
+}
+
+/// Writes an expression as MathML, naming species and parameters by their identifiers in `ids`.
+fn mathml(expr: &Expr, ids: &IdMap) -> String {
+    /// Writes an `<apply>` element.
+    fn apply(op: &str, args: &[&Expr], ids: &IdMap) -> String {
+        let args: String = args.iter().map(|arg| mathml(arg, ids)).collect();
+        format!("<apply><{op}/>{args}</apply>")
+    }
+
+    match expr {
+        Expr::Num(x) => format!("<cn>{x}</cn>"),
+        Expr::Species(i) => format!("<ci>{}</ci>", ids.species[*i]),
+        Expr::Param(name, value) => match ids.params.get(name) {
+            Some(id) => format!("<ci>{id}</ci>"),
+            None => format!("<cn>{value}</cn>"),
+        },
+        Expr::Neg(e) => apply("minus", &[e], ids),
+        Expr::BinOp(op, a, b) => {
+            /// Collects the operands of a chain of the same associative operator.
+            fn operands<'e>(e: &'e Expr, op: BinOp, out: &mut Vec<&'e Expr>) {
+                match e {
+                    Expr::BinOp(inner, a, b) if *inner == op => {
+                        operands(a, op, out);
+                        operands(b, op, out);
+                    }
+                    e => out.push(e),
+                }
+            }
+            let mut args = Vec::new();
+            if matches!(op, BinOp::Add | BinOp::Mul) {
+                operands(a, *op, &mut args);
+                operands(b, *op, &mut args);
+            } else {
+                args.extend([&**a, &**b]);
+            }
+            let op = match op {
+                BinOp::Add => "plus",
+                BinOp::Sub => "minus",
+                BinOp::Mul => "times",
+                BinOp::Div => "divide",
+                BinOp::Pow => "power",
+            };
+            apply(op, &args, ids)
+        }
+        Expr::Call(Func::Exp, args) => apply("exp", &[&args[0]], ids),
+        Expr::Call(Func::Ln, args) => apply("ln", &[&args[0]], ids),
+        Expr::Call(..) => mathml(&expr.expand(), ids),
+    }
+}
+
+impl<T> Crn<T>
+where
+    T: Display,
+{
+    /// Writes the CRN's initial state and reactions as an SBML Level 3 Version 2 document with a single compartment of unit size.
+    ///
+    /// Species names that are not valid SBML identifiers are kept in the `name` attribute. Reversible pairs of mass-action reactions are written as one reversible reaction. CRNs with events, or with initial amounts given by expressions, are rejected with [`ExportError::Unsupported`].
+    pub fn to_sbml(&self) -> Result<String, ExportError> {
+        self.check_export("SBML")?;
+        let mut ids = Ids::default();
+        let compartment = ids.allocate("compartment");
+        let id_map = IdMap {
+            params: self
+                .params
+                .keys()
+                .map(|name| (name.clone(), ids.allocate(name)))
+                .collect(),
+            species: (0..self.init_state.species.len())
+                .map(|i| match self.names.get_by_left(&i) {
+                    Some(name) => ids.allocate(name),
+                    None => ids.allocate(&format!("s{i}")),
+                })
+                .collect(),
+        };
+        let species_ids = &id_map.species;
+
+        let mut out = String::new();
+        let _ = writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+        let _ = writeln!(out, r#"<sbml xmlns="{SBML_NS}" level="3" version="2">"#);
+        let _ = writeln!(out, r#"  <model id="crn">"#);
+        let _ = writeln!(out, "    <listOfCompartments>");
+        let _ = writeln!(
+            out,
+            r#"      <compartment id="{compartment}" spatialDimensions="3" size="1" constant="true"/>"#
+        );
+        let _ = writeln!(out, "    </listOfCompartments>");
+
+        if !species_ids.is_empty() {
+            let _ = writeln!(out, "    <listOfSpecies>");
+            for (i, (id, amount)) in species_ids.iter().zip(&self.init_state.species).enumerate() {
+                let name = self.names.get_by_left(&i).unwrap_or(id);
+                let _ = writeln!(
+                    out,
+                    r#"      <species id="{id}" name="{}" compartment="{compartment}" initialAmount="{amount}" hasOnlySubstanceUnits="true" boundaryCondition="{}" constant="false"/>"#,
+                    escape(name),
+                    self.boundary.contains(&i)
+                );
+            }
+            let _ = writeln!(out, "    </listOfSpecies>");
+        }
+
+        if !self.params.is_empty() {
+            let _ = writeln!(out, "    <listOfParameters>");
+            for (name, value) in &self.params {
+                let id = &id_map.params[name];
+                let _ = writeln!(
+                    out,
+                    r#"      <parameter id="{id}" value="{value}" constant="true"/>"#
+                );
+            }
+            let _ = writeln!(out, "    </listOfParameters>");
+        }
+
+        let species_refs = |list: &str, stoichiometry: &HashMap<usize, f64>| {
+            if stoichiometry.is_empty() {
+                return String::new();
+            }
+            let refs: String = stoichiometry
+                .iter()
+                .sorted_by_key(|(species, _)| **species)
+                .map(|(species, count)| {
+                    format!(
+                        "\n          <speciesReference species=\"{}\" stoichiometry=\"{count}\" constant=\"true\"/>",
+                        species_ids[*species]
+                    )
+                })
+                .collect();
+            format!("\n        <{list}>{refs}\n        </{list}>")
+        };
+
+        if !self.rxns.is_empty() {
+            let _ = writeln!(out, "    <listOfReactions>");
+            for (i, rxn) in self.rxns.iter().enumerate() {
+                let reverse = rxn.reverse.filter(|j| {
+                    self.is_reversible_pair(i, *j)
+                        && rxn.law == KineticLaw::MassAction
+                        && self.rxns[*j].law == KineticLaw::MassAction
+                });
+                let law = match reverse {
+                    Some(j) if j < i => continue,
+                    Some(j) => Expr::BinOp(
+                        BinOp::Sub,
+                        Box::new(rxn.rate_law()),
+                        Box::new(self.rxns[j].rate_law()),
+                    ),
+                    None => rxn.rate_law(),
+                };
+                let _ = writeln!(
+                    out,
+                    r#"      <reaction id="{}" reversible="{}">{}{}"#,
+                    ids.allocate(&format!("r{i}")),
+                    reverse.is_some(),
+                    species_refs("listOfReactants", &rxn.reactants),
+                    species_refs("listOfProducts", &rxn.products),
+                );
+                let _ = writeln!(out, "        <kineticLaw>");
+                let _ = writeln!(
+                    out,
+                    r#"          <math xmlns="{MATHML_NS}">{}</math>"#,
+                    mathml(&law, &id_map)
+                );
+                let _ = writeln!(out, "        </kineticLaw>");
+                let _ = writeln!(out, "      </reaction>");
+            }
+            let _ = writeln!(out, "    </listOfReactions>");
+        }
+
+        let _ = writeln!(out, "  </model>");
+        let _ = writeln!(out, "</sbml>");
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parse::ParseError, presets, DetCrn, ExportError, KineticLaw, StoCrn};
+
+    #[test]
+    fn roundtrip() {
+        for preset in [presets::EQUILIBRIUM, presets::PREDATOR_PREY, presets::OTHER] {
+            let crn = StoCrn::parse(preset).unwrap();
+            let imported = StoCrn::from_sbml(&crn.to_sbml().unwrap()).unwrap();
+            assert_eq!(imported.rxns, crn.rxns);
+            assert_eq!(imported.init_state, crn.init_state);
+            assert_eq!(imported.names, crn.names);
+        }
+
+        let crn = StoCrn::parse("$F = 5; A = 1; F + A -> 2A;").unwrap();
+        let imported = StoCrn::from_sbml(&crn.to_sbml().unwrap()).unwrap();
+        assert_eq!(imported.boundary, crn.boundary);
+        assert_eq!(imported.rxns, crn.rxns);
+
+        let crn = DetCrn::parse("param k = 2; A = 1.5; A -> B : k; B -> : hill(B, k, 2);").unwrap();
+        let imported = DetCrn::from_sbml(&crn.to_sbml().unwrap()).unwrap();
+        assert_eq!(imported.params, crn.params);
+        assert_eq!(imported.rxns[0], crn.rxns[0]);
+        assert!(matches!(imported.rxns[1].law, KineticLaw::Custom(_)));
+        assert_eq!(
+            imported.state.rate(&imported.rxns[1]),
+            crn.state.rate(&crn.rxns[1])
+        );
+
+        // parameter names that are not SBML identifiers, or that clash with the compartment
+        let crn = DetCrn::parse(
+            "module m(S) { param k = 3; S -> : k; } A = 1; c: m(A); param compartment = 5; A -> B : compartment;",
+        )
+        .unwrap();
+        let imported = DetCrn::from_sbml(&crn.to_sbml().unwrap()).unwrap();
+        let rates =
+            |crn: &DetCrn| -> Vec<f64> { crn.rxns.iter().map(|rxn| crn.state.rate(rxn)).collect() };
+        assert_eq!(rates(&imported), vec![3.0, 5.0]);
+        assert_eq!(rates(&imported), rates(&crn));
+    }
+
+    #[test]
+    fn import() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<sbml xmlns="http://www.sbml.org/sbml/level3/version1/core" level="3" version="1">
+  <model id="m">
+    <listOfCompartments>
+      <compartment id="cell" size="2" constant="true"/>
+    </listOfCompartments>
+    <listOfSpecies>
+      <species id="S" compartment="cell" initialConcentration="5" hasOnlySubstanceUnits="false" boundaryCondition="false" constant="false"/>
+      <species id="P" name="Product" compartment="cell" initialAmount="0" hasOnlySubstanceUnits="true" boundaryCondition="false" constant="false"/>
+    </listOfSpecies>
+    <listOfReactions>
+      <reaction id="J0" reversible="false" fast="false">
+        <listOfReactants><speciesReference species="S" stoichiometry="2" constant="true"/></listOfReactants>
+        <listOfProducts><speciesReference species="P" constant="true"/></listOfProducts>
+        <kineticLaw>
+          <math xmlns="http://www.w3.org/1998/Math/MathML">
+            <apply><times/><ci>k</ci><ci>S</ci><ci>S</ci></apply>
+          </math>
+          <listOfLocalParameters><localParameter id="k" value="0.25"/></listOfLocalParameters>
+        </kineticLaw>
+      </reaction>
+    </listOfReactions>
+  </model>
+</sbml>"#;
+        let crn = StoCrn::from_sbml(xml).unwrap();
+        assert_eq!(crn.init_state.species, vec![10, 0]);
+        assert_eq!(crn.names.get_by_left(&1).unwrap(), "Product");
+        assert_eq!(crn.rxns[0].reactants[&0], 2.0);
+        // the law uses the concentration of `S`, which is 5
+        assert_eq!(crn.state.rate(&crn.rxns[0]), 0.25 * 5.0 * 5.0);
+
+        let xml = xml.replace(
+            r#"initialConcentration="5" hasOnlySubstanceUnits="false""#,
+            r#"initialAmount="10" hasOnlySubstanceUnits="true""#,
+        );
+        let crn = StoCrn::from_sbml(&xml).unwrap();
+        assert_eq!(crn.rxns[0].rate, 0.25);
+        assert_eq!(crn.rxns[0].law, KineticLaw::MassAction);
+
+        let xml = r#"<sbml level="3" version="2"><model>
+  <listOfCompartments><compartment id="c" size="2"/></listOfCompartments>
+  <listOfSpecies><species id="S" compartment="c" initialConcentration="5" hasOnlySubstanceUnits="false"/></listOfSpecies>
+  <listOfParameters><parameter id="k" value="1"/></listOfParameters>
+  <listOfReactions><reaction id="J0"><listOfReactants><speciesReference species="S"/></listOfReactants>
+    <kineticLaw><math><apply><times/><ci>c</ci><ci>k</ci><ci>S</ci></apply></math></kineticLaw>
+  </reaction></listOfReactions>
+</model></sbml>"#;
+        let mut crn = DetCrn::from_sbml(xml).unwrap();
+        assert_eq!(crn.state.rate(&crn.rxns[0]), 10.0);
+        // the law keeps following `k`, although it has a constant factor
+        crn.set_parameter("k", 2.0).unwrap();
+        assert_eq!(crn.state.rate(&crn.rxns[0]), 20.0);
+
+        // a species named after the id of another, and a parameter with the name of a species
+        for model in [
+            r#"<listOfSpecies><species id="A" name="B"/><species id="B"/></listOfSpecies>"#,
+            r#"<listOfSpecies><species id="A"/></listOfSpecies><listOfParameters><parameter id="A" value="1"/></listOfParameters>"#,
+        ] {
+            let xml = format!(r#"<sbml level="3" version="2"><model>{model}</model></sbml>"#);
+            assert!(matches!(
+                StoCrn::from_sbml(&xml),
+                Err(ParseError::DuplicateDefinition { name, .. }) if name == "B" || name == "A"
+            ));
+        }
+    }
+
+    #[test]
+    fn unsupported() {
+        let xml = r#"<sbml level="3" version="2"><model>
+  <listOfEvents><event id="e"/></listOfEvents>
+</model></sbml>"#;
+        match StoCrn::from_sbml(xml) {
+            Err(ParseError::Unsupported {
+                construct,
+                location,
+            }) => {
+                assert_eq!(construct, "events");
+                assert_eq!(location.line, 2);
+            }
+            e => panic!("unexpected result {e:?}"),
+        }
+        assert!(matches!(
+            StoCrn::from_sbml(r#"<sbml level="2" version="4"><model/></sbml>"#),
+            Err(ParseError::Unsupported { construct, .. }) if construct == "SBML level 2"
+        ));
+        assert!(matches!(
+            StoCrn::from_sbml("<sbml><model>"),
+            Err(ParseError::Syntax { .. })
+        ));
+
+        let crn = StoCrn::parse("A = 1; A -> B; at 1: A = 0;").unwrap();
+        assert!(matches!(
+            crn.to_sbml(),
+            Err(ExportError::Unsupported { construct, .. }) if construct == "events"
+        ));
+    }
+}
@@ -0,0 +1,106 @@
+/// Serializes a map, such as a reaction's stoichiometry, with its entries sorted by key.
+pub(crate) mod sorted_map {
+    use std::collections::{BTreeMap, HashMap};
+    use std::hash::Hash;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    /// Writes the map in key order.
+    pub(crate) fn serialize<S, K, V>(map: &HashMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        K: Ord + Serialize,
+        V: Serialize,
+    {
+        map.iter().collect::<BTreeMap<_, _>>().serialize(serializer)
+    }
+
+    /// Reads a map written by [`serialize`].
+    pub(crate) fn deserialize<'de, D, K, V>(deserializer: D) -> Result<HashMap<K, V>, D::Error>
+    where
+        D: Deserializer<'de>,
+        K: Ord + Hash + Deserialize<'de>,
+        V: Deserialize<'de>,
+    {
+        Ok(BTreeMap::deserialize(deserializer)?.into_iter().collect())
+    }
+}
+
+/// Serializes species names as a map from species index to name, in index order.
+pub(crate) mod names {
+    use std::collections::BTreeMap;
+
+    use bimap::BiHashMap;
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    /// Writes the names in index order.
+    pub(crate) fn serialize<S>(
+        names: &BiHashMap<usize, String>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        names
+            .iter()
+            .collect::<BTreeMap<_, _>>()
+            .serialize(serializer)
+    }
+
+    /// Reads names written by [`serialize`]. Two species cannot share a name.
+    pub(crate) fn deserialize<'de, D>(deserializer: D) -> Result<BiHashMap<usize, String>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let mut names = BiHashMap::new();
+        for (idx, name) in BTreeMap::<usize, String>::deserialize(deserializer)? {
+            if names.contains_right(&name) {
+                return Err(D::Error::custom(format!("duplicate species name `{name}`")));
+            }
+            names.insert(idx, name);
+        }
+        Ok(names)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{presets, DetCrn, State, StoCrn};
+
+    #[test]
+    fn json_roundtrip() {
+        let crn = StoCrn::parse(presets::EQUILIBRIUM).unwrap();
+        let json = serde_json::to_string(&crn).unwrap();
+        assert_eq!(json, serde_json::to_string(&crn.clone()).unwrap());
+        let read: StoCrn = serde_json::from_str(&json).unwrap();
+        assert_eq!(read.rxns, crn.rxns);
+        assert_eq!(read.init_state, crn.init_state);
+        assert_eq!(read.names, crn.names);
+
+        let mut crn =
+            DetCrn::parse("param k = 2; A = 1.5; A -> B : k; B -> : hill(B, k, 2);").unwrap();
+        let read: DetCrn = serde_json::from_str(&serde_json::to_string(&crn).unwrap()).unwrap();
+        assert_eq!(read.rxns, crn.rxns);
+        assert_eq!(read.params, crn.params);
+
+        let history = crn.simulate_history(0.01, 0.001).unwrap();
+        let json = serde_json::to_string(&history).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Vec<State<f64>>>(&json).unwrap(),
+            history
+        );
+    }
+
+    #[test]
+    fn readable() {
+        let crn = StoCrn::parse("A = 2; B = 0; 2A -> B : 0.5;").unwrap();
+        let json = serde_json::to_value(&crn).unwrap();
+        assert_eq!(json["names"], serde_json::json!({"0": "A", "1": "B"}));
+        assert_eq!(json["rxns"][0]["reactants"], serde_json::json!({"0": 2.0}));
+        assert_eq!(json["rxns"][0]["rate"], 0.5);
+        assert_eq!(json["init_state"]["species"], serde_json::json!([2, 0]));
+
+        let duplicate = r#"{"rxns": [], "state": {"species": [1, 2], "time": 0}, "init_state": {"species": [1, 2], "time": 0}, "names": {"0": "A", "1": "A"}, "params": {}}"#;
+        assert!(serde_json::from_str::<StoCrn>(duplicate).is_err());
+    }
+}
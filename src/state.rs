@@ -1,7 +1,8 @@
-use crate::Reaction;
+use crate::{KineticLaw, Reaction};
 
 /// A state of a CRN. StoCrn uses integers, DetCrn uses floats.
 #[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct State<T> {
     /// Amount of each species. Will be an integer for stochastic CRNs, and a float for deterministic CRNs.
     pub species: Vec<T>,
@@ -13,7 +14,7 @@ impl State<i32> {
     /// Applies a reaction, modifying the amounts of each species.
     pub fn apply(&mut self, rxn: &Reaction) {
         for (i, d) in rxn.delta.iter() {
-            self.species[*i] += d;
+            self.species[*i] += *d as i32;
         }
     }
 
@@ -21,22 +22,27 @@ impl State<i32> {
     pub fn applicable(&self, rxn: &Reaction) -> bool {
         rxn.reactants
             .iter()
-            .all(|(species, count)| count <= &self.species[*species])
+            .all(|(species, count)| *count <= self.species[*species] as f64)
     }
 
-    /// Returns the rate at which this reaction is occurring -- if the reactants are more abundant, this will be higher. Note that this is scaled by the rate parameter of the reaction.
+    /// Returns the rate at which this reaction is occurring -- if the reactants are more abundant, this will be higher. Note that this is scaled by the rate parameter of the reaction. Custom rate laws are clamped to be non-negative.
     pub fn rate(&self, rxn: &Reaction) -> f64 {
-        if self.applicable(rxn) {
-            rxn.reactants
-                .iter()
-                .fold(rxn.rate, |mut cur, (species, count)| {
-                    for i in (self.species[*species] - count + 1)..=self.species[*species] {
-                        cur *= i as f64
-                    }
-                    cur
-                })
-        } else {
-            0.0
+        if !self.applicable(rxn) {
+            return 0.0;
+        }
+        match &rxn.law {
+            KineticLaw::MassAction => {
+                rxn.reactants
+                    .iter()
+                    .fold(rxn.rate, |mut cur, (species, count)| {
+                        let count = *count as i32;
+                        for i in (self.species[*species] - count + 1)..=self.species[*species] {
+                            cur *= i as f64
+                        }
+                        cur
+                    })
+            }
+            KineticLaw::Custom(law) => law.eval(&self.species).max(0.0),
         }
     }
 }
@@ -44,11 +50,15 @@ impl State<i32> {
 impl State<f64> {
     /// Returns the rate at which this reaction is occurring -- if the reactants are more abundant, this will be higher. Note that this is scaled by the rate parameter of the reaction.
     pub fn rate(&self, rxn: &Reaction) -> f64 {
-        rxn.reactants
-            .iter()
-            .fold(rxn.rate, |cur, (species, count)| {
-                cur * self.species[*species].powi(*count)
-            })
+        match &rxn.law {
+            KineticLaw::MassAction => rxn
+                .reactants
+                .iter()
+                .fold(rxn.rate, |cur, (species, count)| {
+                    cur * self.species[*species].powf(*count)
+                }),
+            KineticLaw::Custom(law) => law.eval(&self.species),
+        }
     }
 
     /// Given a set of reactions, returns the instantaneous rate of change of each species.
@@ -60,7 +70,7 @@ impl State<f64> {
         rxns.iter().for_each(|rxn| {
             let rate = self.rate(rxn);
             for (species, change) in &rxn.delta {
-                res.species[*species] += *change as f64 * rate;
+                res.species[*species] += change * rate;
             }
         });
         res
@@ -1,6 +1,10 @@
-use rand::Rng;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 
-use crate::{state::State, Crn};
+use crate::{
+    cr::CompositionRejection, nrm::NextReaction, odm::OptimizedDirect, state::State,
+    tau::TauLeaping, Crn,
+};
 
 use std::fmt::Debug;
 
@@ -15,14 +19,98 @@ pub enum Error {
     /// The simulation has become numerically unstable.
     #[error("Insufficient precision for accurate simulation")]
     InsufficientPrecision,
+    /// A species amount has become too large to count.
+    #[error("A species amount is too large to count")]
+    Overflow,
 }
 
 /// A stochastic CRN. This is simulated using the Gillespie algorithm. Stochastic CRNs are essentially a type of continuous-time Markov chain.
+///
+/// Simulations draw from the thread's random number generator, or from any [`Rng`] passed to the `_with_rng` methods. A generator from [`seeded_rng`] makes a simulation reproducible: the same seed gives a bit-identical trajectory.
 pub type StoCrn = Crn<i32>;
 
+/// A random number generator that always produces the same numbers from the same seed, on every platform.
+pub fn seeded_rng(seed: u64) -> ChaCha8Rng {
+    ChaCha8Rng::seed_from_u64(seed)
+}
+
+/// A random time until the next of the reactions that happen at a total rate of `rate`, which must be positive. Waiting times are exponentially distributed.
+pub(crate) fn waiting_time<R: Rng + ?Sized>(rate: f64, rng: &mut R) -> f64 {
+    // the random number is in (0, 1], so the ln is negative or zero and the time is positive or zero
+    -(1.0 - rng.gen::<f64>()).ln() / rate
+}
+
+/// How a stochastic simulation chooses the next reaction and when it happens. Every method except tau-leaping simulates the same Markov chain, so trajectories have the same statistics; they differ in speed.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Method {
+    /// Gillespie's direct method, which computes every reaction's rate before each reaction. Best for small networks.
+    #[default]
+    Direct,
+    /// Gibson and Bruck's next reaction method, which keeps a putative firing time for each reaction in a priority queue and, after each reaction, only updates the reactions whose rates it changed. Best for large networks where each reaction affects few others.
+    NextReaction,
+    /// The optimized direct method, which chooses reactions as the direct method does but only recomputes the rates a reaction changed, keeps a running total of the rates, and searches the reactions that fire most often first. Much faster than the direct method on large networks.
+    OptimizedDirect,
+    /// The composition–rejection method, which groups reactions by the power of two their rate falls between, chooses a group in proportion to its total rate, and then a reaction in it by rejection sampling. Choosing a reaction takes about constant time, so it suits networks with tens of thousands of reactions.
+    CompositionRejection,
+    /// Adaptive tau-leaping, as described by Cao, Gillespie and Petzold. Each leap fires many reactions at once, over a time in which no rate should change by more than the fraction `epsilon`, such as 0.03. Smaller values are more accurate and slower. Amounts never become negative, and when they are too small to leap over, reactions are simulated exactly. Approximate, but far faster when amounts are large.
+    ///
+    /// The state is recorded after each leap in which something fires, rather than after each reaction. A leap whose amounts would not fit in an `i32` fails with [`Error::Overflow`].
+    TauLeaping {
+        /// The largest relative change in any rate allowed during a leap.
+        epsilon: f64,
+    },
+}
+
+/// A way of simulating a stochastic CRN one reaction at a time, which may keep its own bookkeeping between reactions.
+pub(crate) trait Engine {
+    /// Simulate one reaction, or one leap of several, unless it would happen after time `limit`. Then the time advances to `limit` instead. Returns whether any reaction happened.
+    fn step_until<R: Rng + ?Sized>(
+        &mut self,
+        crn: &mut StoCrn,
+        limit: f64,
+        rng: &mut R,
+    ) -> Result<bool, Error>;
+
+    /// Catches up with a change to the state that the engine did not make, such as an event.
+    fn refresh<R: Rng + ?Sized>(&mut self, crn: &StoCrn, rng: &mut R);
+}
+
+/// Gillespie's direct method.
+struct Direct {
+    /// The rate of each reaction, kept to avoid repeated allocations.
+    rates: Vec<f64>,
+}
+
+impl Direct {
+    /// Prepares to simulate `crn`.
+    fn new(crn: &StoCrn) -> Self {
+        Self {
+            rates: vec![0.0; crn.rxns.len()],
+        }
+    }
+}
+
+impl Engine for Direct {
+    fn step_until<R: Rng + ?Sized>(
+        &mut self,
+        crn: &mut StoCrn,
+        limit: f64,
+        rng: &mut R,
+    ) -> Result<bool, Error> {
+        crn.step_until(&mut self.rates, limit, rng)
+    }
+
+    fn refresh<R: Rng + ?Sized>(&mut self, _crn: &StoCrn, _rng: &mut R) {}
+}
+
 impl StoCrn {
-    /// Simulate one reaction. Uses `rates` to avoid repeated allocations.
-    fn step(&mut self, rates: &mut [f64]) -> Result<(), Error> {
+    /// Simulate one reaction, unless it would happen after time `limit`. Then the time advances to `limit` instead, which is exact because the waiting time for a reaction does not depend on how long the CRN has already waited. Returns whether a reaction happened.
+    pub(crate) fn step_until<R: Rng + ?Sized>(
+        &mut self,
+        rates: &mut [f64],
+        limit: f64,
+        rng: &mut R,
+    ) -> Result<bool, Error> {
         let mut rate = 0.0;
 
         self.rxns.iter().enumerate().for_each(|(idx, rxn)| {
@@ -32,12 +120,19 @@ impl StoCrn {
         });
 
         if rate == 0.0 {
-            return Err(Error::TerminalState);
+            if limit == f64::INFINITY {
+                return Err(Error::TerminalState);
+            }
+            self.state.time = limit;
+            return Ok(false);
         }
 
-        let mut rng = rand::thread_rng();
-        // the random number is in (0, 1], so the ln is negative or zero and this is really an addition
-        self.state.time -= (1.0 - rng.gen::<f64>()).ln() / rate;
+        let time = self.state.time + waiting_time(rate, rng);
+        if time > limit {
+            self.state.time = limit;
+            return Ok(false);
+        }
+        self.state.time = time;
         let j = rng.gen::<f64>() * rate;
         let mut sum = 0.0;
 
@@ -45,7 +140,7 @@ impl StoCrn {
             sum += cur_rate;
             if j < sum {
                 self.state.apply(&self.rxns[idx]);
-                return Ok(());
+                return Ok(true);
             }
         }
         Err(Error::InsufficientPrecision)
@@ -53,27 +148,134 @@ impl StoCrn {
 
     /// Simulate a number of reactions.
     pub fn steps(&mut self, steps: usize) -> Result<(), Error> {
-        let mut rates = vec![0.0; self.rxns.len()];
+        self.steps_with_rng(steps, &mut rand::thread_rng())
+    }
+
+    /// Simulate a number of reactions, drawing random numbers from `rng`.
+    pub fn steps_with_rng<R: Rng + ?Sized>(
+        &mut self,
+        steps: usize,
+        rng: &mut R,
+    ) -> Result<(), Error> {
+        self.steps_using(steps, Method::Direct, rng)
+    }
+
+    /// Simulate a number of reactions with the given method, drawing random numbers from `rng`.
+    pub fn steps_using<R: Rng + ?Sized>(
+        &mut self,
+        steps: usize,
+        method: Method,
+        rng: &mut R,
+    ) -> Result<(), Error> {
+        match method {
+            Method::Direct => self.run_steps(&mut Direct::new(self), steps, rng),
+            Method::NextReaction => self.run_steps(&mut NextReaction::new(self, rng), steps, rng),
+            Method::OptimizedDirect => self.run_steps(&mut OptimizedDirect::new(self), steps, rng),
+            Method::CompositionRejection => {
+                self.run_steps(&mut CompositionRejection::new(self), steps, rng)
+            }
+            Method::TauLeaping { epsilon } => {
+                self.run_steps(&mut TauLeaping::new(self, epsilon), steps, rng)
+            }
+        }
+    }
+
+    /// Simulate a number of reactions with `engine`.
+    fn run_steps<E: Engine, R: Rng + ?Sized>(
+        &mut self,
+        engine: &mut E,
+        steps: usize,
+        rng: &mut R,
+    ) -> Result<(), Error> {
         for _ in 0..steps {
-            self.step(&mut rates)?;
+            engine.step_until(self, f64::INFINITY, rng)?;
         }
         Ok(())
     }
 
-    /// Simulates for a given amount of time. Returns a collection of individual species' history.
+    /// The current state, with amounts as floating-point numbers.
+    fn record(&self) -> State<f64> {
+        State {
+            species: self.state.species.iter().map(|x| *x as f64).collect(),
+            time: self.state.time,
+        }
+    }
+
+    /// Simulates until the given time. Returns the state after each reaction.
+    ///
+    /// The simulation stops at exactly `t`: a reaction that would happen after it does not, and the time advances to `t` instead. Timed events between now and `t`, including at `t`, happen at exactly their time, and the state is recorded just before and just after each one.
+    ///
+    /// The conditions of triggered events are checked after every reaction and timed event, and the state is recorded again after any that happen.
     pub fn simulate_history(&mut self, t: f64) -> Result<Vec<State<f64>>, Error> {
+        self.simulate_history_with_rng(t, &mut rand::thread_rng())
+    }
+
+    /// Simulates until the given time as [`StoCrn::simulate_history`] does, drawing random numbers from `rng`.
+    pub fn simulate_history_with_rng<R: Rng + ?Sized>(
+        &mut self,
+        t: f64,
+        rng: &mut R,
+    ) -> Result<Vec<State<f64>>, Error> {
+        self.simulate_history_using(t, Method::Direct, rng)
+    }
+
+    /// Simulates until the given time as [`StoCrn::simulate_history`] does, with the given method, drawing random numbers from `rng`.
+    pub fn simulate_history_using<R: Rng + ?Sized>(
+        &mut self,
+        t: f64,
+        method: Method,
+        rng: &mut R,
+    ) -> Result<Vec<State<f64>>, Error> {
+        match method {
+            Method::Direct => self.run_history(&mut Direct::new(self), t, rng),
+            Method::NextReaction => self.run_history(&mut NextReaction::new(self, rng), t, rng),
+            Method::OptimizedDirect => self.run_history(&mut OptimizedDirect::new(self), t, rng),
+            Method::CompositionRejection => {
+                self.run_history(&mut CompositionRejection::new(self), t, rng)
+            }
+            Method::TauLeaping { epsilon } => {
+                self.run_history(&mut TauLeaping::new(self, epsilon), t, rng)
+            }
+        }
+    }
+
+    /// Simulates until the given time with `engine`.
+    fn run_history<E: Engine, R: Rng + ?Sized>(
+        &mut self,
+        engine: &mut E,
+        t: f64,
+        rng: &mut R,
+    ) -> Result<Vec<State<f64>>, Error> {
         let mut result = Vec::new();
 
-        let mut rates = vec![0.0; self.rxns.len()];
+        let mut events = self.timed_events(self.state.time).into_iter().peekable();
+        let mut held = self.conditions();
         while self.state.time < t {
-            if self.step(&mut rates).is_err() {
-                break;
+            let limit = events.peek().map_or(t, |&(at, _)| at.min(t));
+            match engine.step_until(self, limit, rng) {
+                Ok(true) => {
+                    result.push(self.record());
+                    if self.fire_triggered(&mut held) {
+                        engine.refresh(self, rng);
+                        result.push(self.record());
+                    }
+                }
+                Ok(false) => {
+                    let time = self.state.time;
+                    if events.peek().is_none_or(|(at, _)| *at != time) {
+                        // the end time, with no event there
+                        break;
+                    }
+                    result.push(self.record());
+                    while let Some((_, idx)) = events.next_if(|(at, _)| *at == time) {
+                        self.events[idx].apply(&mut self.state);
+                    }
+                    self.fire_triggered(&mut held);
+                    engine.refresh(self, rng);
+                    result.push(self.record());
+                }
+                Err(_) => break,
             }
-            let species = self.state.species.iter().map(|x| *x as f64).collect();
-            result.push(State {
-                species,
-                time: self.state.time,
-            });
         }
         Ok(result)
     }
@@ -81,15 +283,23 @@ impl StoCrn {
 
 #[cfg(test)]
 mod tests {
+    use super::{seeded_rng, Error, Method};
     use crate::StoCrn;
 
+    /// The methods that simulate the same Markov chain exactly.
+    const EXACT: [Method; 4] = [
+        Method::Direct,
+        Method::NextReaction,
+        Method::OptimizedDirect,
+        Method::CompositionRejection,
+    ];
+
     #[test]
     fn test() {
         const N: i32 = 100;
         let mut crn = StoCrn::parse(&format!("A = {N}; A -> ;")).unwrap();
-        let mut rates = vec![0.0; crn.rxns.len()];
         for i in (0..N).rev() {
-            crn.step(&mut rates).unwrap();
+            crn.steps(1).unwrap();
             assert_eq!(crn.state.species[0], i);
         }
     }
@@ -97,12 +307,11 @@ mod tests {
     #[test]
     fn test2() {
         let mut crn = StoCrn::parse("A = 1; B = 1; A + B -> C; C -> A + B;").unwrap();
-        let mut rates = vec![0.0; crn.rxns.len()];
-        crn.step(&mut rates).unwrap();
+        crn.steps(1).unwrap();
         assert_eq!(crn.state.species[0], 0);
         assert_eq!(crn.state.species[1], 0);
         assert_eq!(crn.state.species[2], 1);
-        crn.step(&mut rates).unwrap();
+        crn.steps(1).unwrap();
         assert_eq!(crn.state.species[0], 1);
         assert_eq!(crn.state.species[1], 1);
         assert_eq!(crn.state.species[2], 0);
@@ -118,4 +327,101 @@ mod tests {
         assert_eq!(crn.state.species[3], 0);
         assert_eq!(crn.state.species[4], 1);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn boundary() {
+        let mut crn = StoCrn::parse("$F = 5; F -> A; 2F -> B;").unwrap();
+        crn.steps(100).unwrap();
+        assert_eq!(crn.state.species[0], 5);
+        assert_eq!(crn.state.species[1] + crn.state.species[2], 100);
+    }
+
+    #[test]
+    fn methods() {
+        for (seed, method) in (0..).zip(EXACT) {
+            let mut rng = seeded_rng(seed);
+            let mut crn = StoCrn::parse("A = 1000; A -> ;").unwrap();
+            crn.simulate_history_using(1.0, method, &mut rng).unwrap();
+            // the mean is 1000 / e, with a standard deviation of about 15
+            let amount = crn.state.species[0];
+            assert!((300..440).contains(&amount), "{method:?}: {amount}");
+
+            // `A` is made at a rate given by `B`, which it does not react with
+            let mut crn = StoCrn::parse("-> B : 1; -> A : B;").unwrap();
+            let runs = 100;
+            let mut total = 0;
+            for _ in 0..runs {
+                crn.reset();
+                crn.simulate_history_using(10.0, method, &mut rng).unwrap();
+                total += crn.state.species[1];
+            }
+            let mean = total as f64 / runs as f64;
+            assert!((mean - 50.0).abs() < 8.0, "{method:?}: mean {mean}");
+
+            let mut crn = StoCrn::parse("A = 5; A -> ;").unwrap();
+            crn.steps_using(5, method, &mut rng).unwrap();
+            assert!(
+                matches!(
+                    crn.steps_using(1, method, &mut rng),
+                    Err(Error::TerminalState)
+                ),
+                "{method:?}"
+            );
+
+            // no reaction can be chosen in proportion to an infinite rate; the next reaction method fires it at once instead
+            if method != Method::NextReaction {
+                let mut crn = StoCrn::parse("A = 1; B = 0; -> C : A / B;").unwrap();
+                assert!(
+                    matches!(
+                        crn.steps_using(1, method, &mut rng),
+                        Err(Error::InsufficientPrecision)
+                    ),
+                    "{method:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn seeded() {
+        let mut crn = StoCrn::parse(crate::presets::PREDATOR_PREY).unwrap();
+        let first = crn
+            .simulate_history_with_rng(1.0, &mut seeded_rng(7))
+            .unwrap();
+        crn.reset();
+        let second = crn
+            .simulate_history_with_rng(1.0, &mut seeded_rng(7))
+            .unwrap();
+        assert_eq!(first, second);
+        crn.reset();
+        let other = crn
+            .simulate_history_with_rng(1.0, &mut seeded_rng(8))
+            .unwrap();
+        assert_ne!(first, other);
+
+        crn.reset();
+        crn.steps_with_rng(100, &mut seeded_rng(7)).unwrap();
+        assert_eq!(crn.state.time, first[99].time);
+    }
+
+    #[test]
+    fn stops_at_end() {
+        // a pending event after the end does not change where the run stops
+        let mut crn = StoCrn::parse("A = 100; A -> B : 0.5;").unwrap();
+        let mut with_event = StoCrn::parse("A = 100; A -> B : 0.5; at 50: A += 1;").unwrap();
+        for seed in 0..10 {
+            crn.reset();
+            with_event.reset();
+            let history = crn
+                .simulate_history_with_rng(1.0, &mut seeded_rng(seed))
+                .unwrap();
+            let history_with_event = with_event
+                .simulate_history_with_rng(1.0, &mut seeded_rng(seed))
+                .unwrap();
+            assert_eq!(history, history_with_event);
+            assert_eq!(crn.state, with_event.state);
+            assert_eq!(crn.state.time, 1.0);
+            assert!(history.iter().all(|state| state.time <= 1.0));
+        }
+    }
+}
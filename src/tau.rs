@@ -0,0 +1,310 @@
+use rand::Rng;
+use rand_distr::{Distribution, Poisson};
+
+use crate::{
+    state::State,
+    sto::{waiting_time, Engine, Error, StoCrn},
+    KineticLaw,
+};
+
+/// A reaction is critical if fewer than this many more firings would use up one of its reactants. Critical reactions fire at most once per leap.
+const CRITICAL_FIRINGS: f64 = 10.0;
+
+/// A leap shorter than this many expected waiting times between reactions is not worth taking, and exact simulation is used instead.
+const MIN_LEAP: f64 = 10.0;
+
+/// The number of exact reactions simulated when a leap is not worth taking, before trying to leap again.
+const EXACT_STEPS: usize = 100;
+
+/// How much a reaction's rate can change relative to a small relative change in one of its reactants: the reactant's `g_i` in the tau selection of Cao, Gillespie and Petzold. `order` is the total number of reactant molecules, `count` how many of them are this species, and `amount` its amount.
+fn sensitivity(order: u32, count: u32, amount: f64) -> f64 {
+    match (order, count) {
+        (2, 2) => 2.0 + 1.0 / (amount - 1.0),
+        (3, 2) => 1.5 * (2.0 + 1.0 / (amount - 1.0)),
+        (3, 3) => 3.0 + 1.0 / (amount - 1.0) + 2.0 / (amount - 2.0),
+        (order, _) => order as f64,
+    }
+}
+
+/// Adaptive tau-leaping, as described by Cao, Gillespie and Petzold. Each leap fires every reaction a Poisson-distributed number of times, over a time chosen so that no rate should change by more than a fraction `epsilon` of itself. Reactions that could use up a reactant fire at most once per leap, and a leap that would still make an amount negative is retried with half the time. When leaps would be too short to help, as when amounts are small, it simulates reactions exactly instead.
+pub(crate) struct TauLeaping {
+    /// The largest relative change in any rate allowed during a leap.
+    epsilon: f64,
+    /// The rate of each reaction in the current state.
+    rates: Vec<f64>,
+    /// For each species, the largest order of a reaction it is a reactant of, and how many molecules of it that reaction uses.
+    highest_order: Vec<(u32, u32)>,
+    /// The number of exact reactions left to simulate before leaping again.
+    exact_steps: usize,
+}
+
+impl TauLeaping {
+    /// Prepares to simulate `crn`.
+    pub(crate) fn new(crn: &StoCrn, epsilon: f64) -> Self {
+        let mut highest_order = vec![(0, 0); crn.state.species.len()];
+        for rxn in &crn.rxns {
+            let order = rxn.reactants.values().sum::<f64>() as u32;
+            for (species, count) in &rxn.reactants {
+                highest_order[*species] = highest_order[*species].max((order, *count as u32));
+            }
+            if let KineticLaw::Custom(law) = &rxn.law {
+                for species in law.species() {
+                    highest_order[species] = highest_order[species].max((1, 1));
+                }
+            }
+        }
+        Self {
+            epsilon,
+            rates: vec![0.0; crn.rxns.len()],
+            highest_order,
+            exact_steps: 0,
+        }
+    }
+
+    /// How many more times a reaction can fire before it uses up one of its reactants.
+    fn firings_left(crn: &StoCrn, rxn: usize) -> f64 {
+        crn.rxns[rxn]
+            .delta
+            .iter()
+            .filter(|(_, change)| **change < 0.0)
+            .map(|(species, change)| (crn.state.species[*species] as f64 / -change).floor())
+            .fold(f64::INFINITY, f64::min)
+    }
+
+    /// The longest leap over which the rates of the reactions that are not critical should each change by at most a fraction `epsilon`, counting only those reactions' firings.
+    fn leap_time(&self, crn: &StoCrn, critical: &[bool]) -> f64 {
+        let species = crn.state.species.len();
+        let (mut mean, mut variance) = (vec![0.0; species], vec![0.0; species]);
+        let mut reactant = vec![false; species];
+        for (idx, rxn) in crn.rxns.iter().enumerate() {
+            if critical[idx] || self.rates[idx] == 0.0 {
+                continue;
+            }
+            for (species, change) in &rxn.delta {
+                mean[*species] += change * self.rates[idx];
+                variance[*species] += change * change * self.rates[idx];
+            }
+            for species in rxn.rate_species() {
+                reactant[species] = true;
+            }
+        }
+        (0..species)
+            .filter(|species| reactant[*species])
+            .map(|species| {
+                let amount = crn.state.species[species] as f64;
+                let (order, count) = self.highest_order[species];
+                let bound = (self.epsilon * amount / sensitivity(order, count, amount)).max(1.0);
+                (bound / mean[species].abs()).min(bound * bound / variance[species])
+            })
+            .fold(f64::INFINITY, f64::min)
+    }
+
+    /// Takes a leap of at most `leap`, ending at `limit` if it would go past it, given the current rates and which reactions are critical. A leap that would make an amount negative is retried with half the time. Returns the time the leap would end at if no reaction fired in it, in which case the state is left as it was.
+    fn leap<R: Rng + ?Sized>(
+        &self,
+        crn: &mut StoCrn,
+        critical: &[bool],
+        mut leap: f64,
+        limit: f64,
+        rng: &mut R,
+    ) -> Result<Option<f64>, Error> {
+        let critical_total: f64 = (0..crn.rxns.len())
+            .filter(|idx| critical[*idx])
+            .map(|idx| self.rates[idx])
+            .sum();
+        let until_critical = if critical_total > 0.0 {
+            waiting_time(critical_total, rng)
+        } else {
+            f64::INFINITY
+        };
+        loop {
+            let (mut tau, mut fire_critical) = if leap < until_critical {
+                (leap, false)
+            } else {
+                (until_critical, true)
+            };
+            let end = if crn.state.time + tau > limit {
+                tau = limit - crn.state.time;
+                fire_critical = false;
+                limit
+            } else {
+                crn.state.time + tau
+            };
+
+            let mut firings = vec![0.0; crn.rxns.len()];
+            for (idx, rate) in self.rates.iter().enumerate() {
+                if critical[idx] || *rate <= 0.0 {
+                    continue;
+                }
+                // a mean too small to represent fires nothing, and one that is not finite cannot be sampled
+                let mean = rate * tau;
+                if !mean.is_finite() {
+                    return Err(Error::InsufficientPrecision);
+                }
+                if mean > 0.0 {
+                    let poisson = Poisson::new(mean).map_err(|_| Error::InsufficientPrecision)?;
+                    firings[idx] = poisson.sample(rng);
+                }
+            }
+            if fire_critical {
+                let mut target = rng.gen::<f64>() * critical_total;
+                let chosen = (0..crn.rxns.len())
+                    .filter(|idx| critical[*idx])
+                    .find(|idx| {
+                        target -= self.rates[*idx];
+                        target < 0.0
+                    });
+                if let Some(idx) = chosen {
+                    firings[idx] = 1.0;
+                }
+            }
+            if firings.iter().all(|count| *count == 0.0) {
+                return Ok(Some(end));
+            }
+
+            let mut species: Vec<f64> = crn.state.species.iter().map(|x| *x as f64).collect();
+            for (rxn, count) in crn.rxns.iter().zip(&firings) {
+                for (idx, change) in &rxn.delta {
+                    species[*idx] += count * change;
+                }
+            }
+            if species.iter().all(|amount| *amount >= 0.0) {
+                if species.iter().any(|amount| *amount > i32::MAX as f64) {
+                    return Err(Error::Overflow);
+                }
+                crn.state = State {
+                    species: species.into_iter().map(|amount| amount as i32).collect(),
+                    time: end,
+                };
+                return Ok(None);
+            }
+            leap /= 2.0;
+        }
+    }
+}
+
+impl Engine for TauLeaping {
+    fn step_until<R: Rng + ?Sized>(
+        &mut self,
+        crn: &mut StoCrn,
+        limit: f64,
+        rng: &mut R,
+    ) -> Result<bool, Error> {
+        if crn.state.time >= limit {
+            return Ok(false);
+        }
+        if self.exact_steps > 0 {
+            self.exact_steps -= 1;
+            return crn.step_until(&mut self.rates, limit, rng);
+        }
+
+        loop {
+            for (rate, rxn) in self.rates.iter_mut().zip(&crn.rxns) {
+                *rate = crn.state.rate(rxn);
+            }
+            let total: f64 = self.rates.iter().sum();
+            let critical: Vec<bool> = (0..crn.rxns.len())
+                .map(|idx| self.rates[idx] > 0.0 && Self::firings_left(crn, idx) < CRITICAL_FIRINGS)
+                .collect();
+            let leap = self.leap_time(crn, &critical);
+            if total == 0.0 || !leap.is_finite() || leap < MIN_LEAP / total {
+                self.exact_steps = EXACT_STEPS - 1;
+                return crn.step_until(&mut self.rates, limit, rng);
+            }
+            if let Some(end) = self.leap(crn, &critical, leap, limit, rng)? {
+                // Nothing fired, so the rates are the same at the end of the leap.
+                if end <= crn.state.time {
+                    return Err(Error::InsufficientPrecision);
+                }
+                crn.state.time = end;
+                if end >= limit {
+                    return Ok(false);
+                }
+                continue;
+            }
+            return Ok(true);
+        }
+    }
+
+    fn refresh<R: Rng + ?Sized>(&mut self, _crn: &StoCrn, _rng: &mut R) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sensitivity;
+    use crate::{
+        presets,
+        sto::{seeded_rng, Error, Method},
+        StoCrn,
+    };
+
+    /// Tau-leaping with the usual accuracy.
+    const TAU: Method = Method::TauLeaping { epsilon: 0.03 };
+
+    #[test]
+    fn sensitivities() {
+        assert_eq!(sensitivity(1, 1, 10.0), 1.0);
+        assert_eq!(sensitivity(2, 1, 10.0), 2.0);
+        assert_eq!(sensitivity(2, 2, 11.0), 2.1);
+        assert_eq!(sensitivity(7, 2, 10.0), 7.0);
+    }
+
+    #[test]
+    fn statistics() {
+        let mut rng = seeded_rng(11);
+        let mut crn = StoCrn::parse("A = 10000; A -> ;").unwrap();
+        let history = crn.simulate_history_using(1.0, TAU, &mut rng).unwrap();
+        // the mean is 10000 / e, with a standard deviation of about 48
+        assert!((3450..3900).contains(&crn.state.species[0]));
+        assert!(history.len() < 1000);
+
+        let mut crn = StoCrn::parse("-> A : 1000; A -> : 1;").unwrap();
+        crn.simulate_history_using(10.0, TAU, &mut rng).unwrap();
+        assert!((880..1120).contains(&crn.state.species[0]));
+    }
+
+    #[test]
+    fn small_amounts() {
+        let mut crn = StoCrn::parse("A = 5; A -> ;").unwrap();
+        let mut rng = seeded_rng(12);
+        let history = crn.simulate_history_using(100.0, TAU, &mut rng).unwrap();
+        assert_eq!(history.len(), 5);
+        assert!(matches!(
+            crn.steps_using(1, TAU, &mut rng),
+            Err(Error::TerminalState)
+        ));
+
+        let mut crn = StoCrn::parse(presets::PREDATOR_PREY).unwrap();
+        let history = crn.simulate_history_using(5.0, TAU, &mut rng).unwrap();
+        assert!(history
+            .iter()
+            .all(|state| state.species.iter().all(|amount| *amount >= 0.0)));
+
+        // Leaps keep `2A - B` constant, as every reaction does.
+        let mut crn = StoCrn::parse(presets::EQUILIBRIUM).unwrap();
+        crn.steps_using(200, TAU, &mut rng).unwrap();
+        let [a, b, ..] = crn.state.species[..] else {
+            unreachable!()
+        };
+        assert_eq!(2 * a - b, 10000);
+        assert_ne!(a, 10000);
+    }
+
+    #[test]
+    fn errors() {
+        let mut rng = seeded_rng(13);
+        let mut crn = StoCrn::parse("A = 2000000000; A -> 2A;").unwrap();
+        assert!(matches!(
+            crn.steps_using(100, TAU, &mut rng),
+            Err(Error::Overflow)
+        ));
+        assert!(crn.state.species[0] > 2000000000);
+
+        // a leap cannot sample how often a reaction with an infinite rate fires
+        let mut crn = StoCrn::parse("A = 100; B = 0; A -> ; -> C : A / B;").unwrap();
+        assert!(matches!(
+            crn.steps_using(1, TAU, &mut rng),
+            Err(Error::InsufficientPrecision)
+        ));
+    }
+}